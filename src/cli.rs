@@ -1,12 +1,11 @@
-use clap::{Arg, ArgAction, Command, ValueHint};
-use rime_api::{default_shared_data_dir, default_user_data_dir};
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
 
-pub fn build_cli() -> Command {
-    let user_data_dir = default_user_data_dir().to_string_lossy().to_string();
-    let user_data_dir = Box::leak(user_data_dir.into_boxed_str()) as &'static str;
-    let shared_data_dir = default_shared_data_dir().to_string_lossy().to_string();
-    let shared_data_dir = Box::leak(shared_data_dir.into_boxed_str()) as &'static str;
+use crate::config::{Config, InputBackend};
+use crate::glog::Severity;
 
+/// Options that are also in the config file have no default values here;
+/// the defaults are in [`Config::default`].
+pub fn build_cli() -> Command {
     Command::new("rime-tui")
         .arg(
             Arg::new("schema")
@@ -19,7 +18,6 @@ pub fn build_cli() -> Command {
         .arg(
             Arg::new("shared-dir")
                 .long("shared-dir")
-                .default_value(shared_data_dir)
                 .value_hint(ValueHint::DirPath)
                 .action(ArgAction::Set)
                 .help("Rime shared data directory"),
@@ -27,31 +25,102 @@ pub fn build_cli() -> Command {
         .arg(
             Arg::new("user-dir")
                 .long("user-dir")
-                .default_value(user_data_dir)
                 .value_hint(ValueHint::DirPath)
                 .action(ArgAction::Set)
                 .help("Rime user data directory"),
         )
+        .arg(
+            Arg::new("command-prefix")
+                .long("command-prefix")
+                .action(ArgAction::Set)
+                .help("Prefix of input commands, like \"/\" in \"/exit\" [default: /]"),
+        )
         .arg(
             Arg::new("exit-command")
                 .long("exit-command")
-                .default_value("/exit")
                 .action(ArgAction::Set)
-                .help("Input command for exiting the program"),
+                .help("Deprecated, use `aliases` in the config file: an extra input running /exit, like \"/quit\""),
         )
         .arg(
             Arg::new("copy-command")
                 .long("copy-command")
-                .default_value("/copy")
                 .action(ArgAction::Set)
-                .help("Input command for putting the output into X11 clipboard"),
+                .help("Deprecated, use `aliases` in the config file: an extra input running /copy"),
         )
         .arg(
             Arg::new("load-command")
                 .long("load-command")
                 .action(ArgAction::Set)
-                .default_value("/load")
-                .help("Input command for loading from X11 clipboard"),
+                .help("Deprecated, use `aliases` in the config file: an extra input running /load"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .short('c')
+                .required(false)
+                .value_hint(ValueHint::FilePath)
+                .action(ArgAction::Set)
+                .help("Config file path [default: ~/.config/rime-tui/config.toml]"),
+        )
+        .arg(
+            Arg::new("print-config")
+                .long("print-config")
+                .action(ArgAction::SetTrue)
+                .help("Print the effective configuration and exit"),
+        )
+        .arg(
+            Arg::new("input-backend")
+                .long("input-backend")
+                .value_parser(["x11", "terminal", "evdev"])
+                .action(ArgAction::Set)
+                .help("Where to read key events from. \"terminal\" needs the kitty keyboard protocol [default: x11]"),
+        )
+        .arg(
+            Arg::new("evdev-device")
+                .long("evdev-device")
+                .required(false)
+                .value_hint(ValueHint::FilePath)
+                .action(ArgAction::Set)
+                .help("Keyboard device for the evdev input backend [default: the first one found]"),
+        )
+        .arg(
+            Arg::new("window-id")
+                .long("window-id")
+                .required(false)
+                .action(ArgAction::Set)
+                .help("X11 window ID of this terminal; keys are only captured while it is focused [default: $WINDOWID]"),
+        )
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .required(false)
+                .value_hint(ValueHint::FilePath)
+                .action(ArgAction::Set)
+                .help("Run headless: feed key sequences like \"nihao{space}\" from this file (\"-\" for stdin) line by line, and print the output"),
+        )
+        .arg(
+            Arg::new("edit")
+                .long("edit")
+                .short('e')
+                .required(false)
+                .value_hint(ValueHint::FilePath)
+                .action(ArgAction::Set)
+                .help("Edit this file, e.g. as `GIT_EDITOR=\"rime-tui --edit\"`. \"/done\" writes it back and exits with 0; \"/exit\" exits with 1"),
+        )
+        .arg(
+            Arg::new("print-on-exit")
+                .long("print-on-exit")
+                .action(ArgAction::SetTrue)
+                .help("Print the output to stdout on exit, e.g. for `msg=$(rime-tui --print-on-exit)`"),
+        )
+        .arg(
+            Arg::new("output-file")
+                .long("output-file")
+                .short('o')
+                .required(false)
+                .value_hint(ValueHint::FilePath)
+                .action(ArgAction::Set)
+                .help("Write the output to this file on exit"),
         )
         .arg(
             Arg::new("log-dir")
@@ -60,4 +129,60 @@ pub fn build_cli() -> Command {
                 .required(false)
                 .help("Directory location for log files"),
         )
+        .arg(
+            Arg::new("rime-log-dir")
+                .long("rime-log-dir")
+                .required(false)
+                .value_hint(ValueHint::DirPath)
+                .action(ArgAction::Set)
+                .help("Directory librime writes its own log files to"),
+        )
+        .arg(
+            Arg::new("rime-log-level")
+                .long("rime-log-level")
+                .value_parser(["info", "warning", "error", "fatal"])
+                .action(ArgAction::Set)
+                .help("Minimum severity of librime's log lines [default: info]"),
+        )
+}
+
+/// Overrides `config` with the options given on the command line
+pub fn apply_overrides(config: &mut Config, matches: &ArgMatches) {
+    let get = |id: &str| matches.get_one::<String>(id).cloned();
+    if let Some(v) = get("schema") {
+        config.schema = Some(v);
+    }
+    if let Some(v) = get("shared-dir") {
+        config.shared_dir = v;
+    }
+    if let Some(v) = get("user-dir") {
+        config.user_dir = v;
+    }
+    if let Some(v) = get("command-prefix") {
+        config.command_prefix = v;
+    }
+    for (id, name) in [
+        ("exit-command", "exit"),
+        ("copy-command", "copy"),
+        ("load-command", "load"),
+    ] {
+        if let Some(v) = get(id) {
+            config.aliases.insert(v, name.into());
+        }
+    }
+    if let Some(v) = get("log-dir") {
+        config.log_dir = Some(v);
+    }
+    if let Some(v) = get("rime-log-dir") {
+        config.rime_log_dir = Some(v);
+    }
+    if let Some(v) = get("rime-log-level").and_then(|v| Severity::from_name(&v)) {
+        config.rime_log_level = v;
+    }
+    if let Some(v) = get("input-backend").and_then(|v| InputBackend::from_name(&v)) {
+        config.input_backend = v;
+    }
+    if let Some(v) = get("evdev-device") {
+        config.evdev_device = Some(v);
+    }
 }
@@ -0,0 +1,422 @@
+use std::io;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread::spawn;
+
+use crate::config::UserCommandConfig;
+use crate::event::AppEvent;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandAction {
+    Exit,
+    /// Writes back the file of `--edit` and exits
+    Done,
+    Copy,
+    Load,
+    Save,
+    Open,
+    Append,
+    Undo,
+    Redo,
+    /// Opens the schema picker
+    Schema,
+    /// Toggles the Rime option given as the argument, like `ascii_mode`
+    ToggleOption,
+    /// Switches between the vertical and horizontal candidate layouts
+    ToggleCandidateLayout,
+    /// Hides or shows the Log pane
+    ToggleLog,
+    /// Maximizes the Output pane, or restores the layout
+    ToggleMaximize,
+    /// Scrolls the Log pane to older lines
+    ScrollLogUp,
+    /// Scrolls the Log pane to newer lines
+    ScrollLogDown,
+    /// Opens the fullscreen log viewer
+    LogViewer,
+    /// Sets the minimum severity of the log lines shown to the argument,
+    /// or cycles it without an argument
+    LogSeverity,
+    /// Runs a shell command with the output on its stdin, and inserts its stdout
+    /// at the cursor, or replaces the whole output with it
+    Shell {
+        command: String,
+        replace: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct InputCommand {
+    pub name: String,
+    pub description: String,
+    pub action: CommandAction,
+    /// Commands are run when Return is pressed. For a command taking an argument, without one
+    /// typed after the name, it's asked for in a prompt line, where Return with nothing typed
+    /// runs the command without one.
+    pub takes_argument: bool,
+}
+
+impl InputCommand {
+    pub fn new(name: &str, description: &str, action: CommandAction) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            action,
+            takes_argument: false,
+        }
+    }
+
+    pub fn with_argument(mut self) -> Self {
+        self.takes_argument = true;
+        self
+    }
+}
+
+impl From<&UserCommandConfig> for InputCommand {
+    fn from(c: &UserCommandConfig) -> Self {
+        Self {
+            name: c.name.clone(),
+            description: c.description.clone(),
+            action: CommandAction::Shell {
+                command: c.run.clone(),
+                replace: c.replace,
+            },
+            takes_argument: c.argument,
+        }
+    }
+}
+
+/// Input commands, typed into the preedit as the prefix followed by
+/// the command name and an optional argument, e.g. `/save notes.txt`
+pub struct CommandRegistry {
+    prefix: String,
+    commands: Vec<InputCommand>,
+    /// Other inputs running a command, like `/quit` for `exit`, and the command names
+    aliases: Vec<(String, String)>,
+}
+
+impl CommandRegistry {
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.into(),
+            commands: Vec::new(),
+            aliases: Vec::new(),
+        }
+    }
+
+    pub fn with_builtins(prefix: &str) -> Self {
+        use CommandAction::*;
+        let mut registry = Self::new(prefix);
+        for command in [
+            InputCommand::new("exit", "Exit the program", Exit),
+            InputCommand::new("done", "Write back the edited file and exit", Done),
+            InputCommand::new("copy", "Put the output into X11 clipboard", Copy),
+            InputCommand::new("load", "Load the output from X11 clipboard", Load),
+            InputCommand::new("save", "Save the output into a file", Save).with_argument(),
+            InputCommand::new("open", "Replace the output with a file", Open).with_argument(),
+            InputCommand::new("append", "Append the output to a file", Append).with_argument(),
+            InputCommand::new("undo", "Undo the last edit", Undo),
+            InputCommand::new("redo", "Redo the last undone edit", Redo),
+            InputCommand::new("schema", "Choose a Rime schema", Schema),
+            InputCommand::new(
+                "toggle",
+                "Toggle a Rime option, like ascii_mode",
+                ToggleOption,
+            )
+            .with_argument(),
+            InputCommand::new(
+                "candidates",
+                "Switch between vertical and horizontal candidates",
+                ToggleCandidateLayout,
+            ),
+            InputCommand::new("log", "Hide or show the Log pane", ToggleLog),
+            InputCommand::new("scroll-log-up", "Scroll the Log pane up", ScrollLogUp),
+            InputCommand::new("scroll-log-down", "Scroll the Log pane down", ScrollLogDown),
+            InputCommand::new("view-log", "View and search the whole log", LogViewer),
+            InputCommand::new(
+                "severity",
+                "Only show log lines at or above a severity (info, warning, error, fatal)",
+                LogSeverity,
+            )
+            .with_argument(),
+            InputCommand::new(
+                "maximize",
+                "Maximize or restore the Output pane",
+                ToggleMaximize,
+            ),
+        ] {
+            registry.register(command);
+        }
+        registry
+    }
+
+    /// A command replaces the registered one with the same name
+    pub fn register(&mut self, command: InputCommand) {
+        self.commands.retain(|c| c.name != command.name);
+        self.commands.push(command);
+    }
+
+    /// Makes `input` run the command named `name`, whether `input` has the prefix or not,
+    /// e.g. `/quit` for `exit`. It replaces an alias with the same input.
+    pub fn alias(&mut self, input: &str, name: &str) {
+        self.aliases.retain(|(a, _)| a != input);
+        self.aliases.push((input.into(), name.into()));
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn commands(&self) -> &[InputCommand] {
+        &self.commands
+    }
+
+    pub fn get(&self, name: &str) -> Option<&InputCommand> {
+        self.commands.iter().find(|c| c.name == name)
+    }
+
+    /// Whether `input` is (the start of) a command
+    pub fn is_command_input(&self, input: &str) -> bool {
+        !self.prefix.is_empty() && input.starts_with(&self.prefix)
+    }
+
+    /// Parses `input` into the command and its argument
+    pub fn parse<'a>(&self, input: &'a str) -> Option<(&InputCommand, Option<&'a str>)> {
+        let input = input.trim();
+        let (word, argument) = match input.split_once(char::is_whitespace) {
+            None => (input, None),
+            Some((word, argument)) => (word, Some(argument.trim())),
+        };
+        let argument = argument.filter(|a| !a.is_empty());
+        let name = match self.aliases.iter().find(|(a, _)| a == word) {
+            Some((_, name)) => name.as_str(),
+            None => word.strip_prefix(&self.prefix)?,
+        };
+        self.get(name).map(|c| (c, argument))
+    }
+
+    /// Commands whose names start with the name being typed in `input`
+    pub fn complete(&self, input: &str) -> Vec<&InputCommand> {
+        let Some(typed) = input.strip_prefix(&self.prefix) else {
+            return Vec::new();
+        };
+        if typed.contains(char::is_whitespace) {
+            return Vec::new();
+        }
+        self.commands
+            .iter()
+            .filter(|c| c.name.starts_with(typed))
+            .collect()
+    }
+
+    /// The text to append to `input` to complete the command name, as far as
+    /// the candidates share a common prefix
+    pub fn completion_suffix(&self, input: &str) -> Option<String> {
+        let typed = input.strip_prefix(&self.prefix)?;
+        let candidates = self.complete(input);
+        let first = candidates.first()?;
+        let mut common = first.name.as_str();
+        for c in &candidates[1..] {
+            let len = common
+                .char_indices()
+                .zip(c.name.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map(|((i, a), _)| i + a.len_utf8())
+                .unwrap_or(0);
+            common = &common[..len];
+        }
+        let suffix = &common[typed.len()..];
+        (!suffix.is_empty()).then(|| suffix.into())
+    }
+}
+
+/// Runs [`run_shell`] on its own thread, so the UI isn't blocked meanwhile,
+/// and sends the result as [`AppEvent::ShellDone`]
+pub fn spawn_shell(
+    command: &InputCommand,
+    argument: Option<&str>,
+    input: &str,
+    sender: Sender<AppEvent>,
+) {
+    let CommandAction::Shell {
+        command: shell_command,
+        replace,
+    } = command.action.clone()
+    else {
+        return;
+    };
+    let name = command.name.clone();
+    let argument = argument.map(String::from);
+    let input = String::from(input);
+    spawn(move || {
+        let result = run_shell(&shell_command, argument.as_deref(), &input);
+        let _ = sender.send(AppEvent::ShellDone {
+            name,
+            replace,
+            result,
+        });
+    });
+}
+
+/// Runs `command` with `sh`, feeding `input` to its stdin; `argument` is passed as `$1`.
+///
+/// Returns its stdout.
+pub fn run_shell(command: &str, argument: Option<&str>, input: &str) -> io::Result<String> {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command).arg("rime-tui");
+    if let Some(a) = argument {
+        shell.arg(a);
+    }
+    let mut child = shell
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let input = String::from(input);
+    // write from another thread, so a large stdout won't block us
+    // the command may not read its stdin at all; ignore broken pipes
+    let writer = spawn(move || {
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "`{}` exited with {}",
+            command, output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    fn parse<'a>(registry: &CommandRegistry, input: &'a str) -> Option<(String, Option<&'a str>)> {
+        registry.parse(input).map(|(c, a)| (c.name.clone(), a))
+    }
+
+    fn names(commands: Vec<&InputCommand>) -> Vec<&str> {
+        commands.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_names_and_arguments() {
+        let registry = CommandRegistry::with_builtins("/");
+        assert_eq!(parse(&registry, "/copy"), Some(("copy".into(), None)));
+        assert_eq!(parse(&registry, " /copy  "), Some(("copy".into(), None)));
+        assert_eq!(
+            parse(&registry, "/save  notes.txt "),
+            Some(("save".into(), Some("notes.txt")))
+        );
+        assert_eq!(
+            parse(&registry, "/save my notes.txt"),
+            Some(("save".into(), Some("my notes.txt")))
+        );
+        assert_eq!(parse(&registry, "/save "), Some(("save".into(), None)));
+        assert_eq!(parse(&registry, "/cop"), None);
+        assert_eq!(parse(&registry, "copy"), None);
+        assert_eq!(parse(&registry, ""), None);
+    }
+
+    #[test]
+    fn aliases() {
+        let mut registry = CommandRegistry::with_builtins("/");
+        registry.alias("/quit", "exit");
+        registry.alias("qq", "exit");
+        registry.alias("/w", "save");
+        assert_eq!(parse(&registry, "/quit"), Some(("exit".into(), None)));
+        assert_eq!(parse(&registry, "qq"), Some(("exit".into(), None)));
+        assert_eq!(
+            parse(&registry, "/w notes.txt"),
+            Some(("save".into(), Some("notes.txt")))
+        );
+        // the command names still work
+        assert_eq!(parse(&registry, "/exit"), Some(("exit".into(), None)));
+
+        registry.alias("qq", "copy");
+        assert_eq!(parse(&registry, "qq"), Some(("copy".into(), None)));
+        registry.alias("/nothing", "no-such-command");
+        assert_eq!(parse(&registry, "/nothing"), None);
+    }
+
+    #[test]
+    fn register_replaces_by_name() {
+        let mut registry = CommandRegistry::with_builtins("/");
+        let count = registry.commands().len();
+        registry.register(InputCommand::new("copy", "Copy", CommandAction::Exit));
+        assert_eq!(registry.commands().len(), count);
+        assert_eq!(registry.get("copy").unwrap().action, CommandAction::Exit);
+    }
+
+    #[test]
+    fn complete_names() {
+        let registry = CommandRegistry::with_builtins("/");
+        assert_eq!(
+            names(registry.complete("/scr")),
+            ["scroll-log-up", "scroll-log-down"]
+        );
+        assert_eq!(names(registry.complete("/copy")), ["copy"]);
+        assert!(registry.complete("/save notes").is_empty());
+        assert!(registry.complete("scr").is_empty());
+        assert_eq!(registry.complete("/").len(), registry.commands().len());
+
+        assert_eq!(registry.completion_suffix("/scr"), Some("oll-log-".into()));
+        assert_eq!(registry.completion_suffix("/sa"), Some("ve".into()));
+        assert_eq!(registry.completion_suffix("/s"), None);
+        assert_eq!(registry.completion_suffix("/save"), None);
+        assert_eq!(registry.completion_suffix("/x"), None);
+    }
+
+    #[test]
+    fn command_input_needs_a_prefix() {
+        assert!(CommandRegistry::new("/").is_command_input("/sa"));
+        assert!(!CommandRegistry::new("/").is_command_input("sa"));
+        assert!(!CommandRegistry::new("").is_command_input("sa"));
+    }
+
+    #[test]
+    fn shell_commands() {
+        assert_eq!(run_shell("tr a-z A-Z", None, "abc").unwrap(), "ABC");
+        assert_eq!(
+            run_shell("printf '%s' \"$1\"", Some("a b"), "").unwrap(),
+            "a b"
+        );
+        // not reading stdin is fine
+        assert_eq!(
+            run_shell("echo done", None, &"x".repeat(1 << 20)).unwrap(),
+            "done\n"
+        );
+        assert!(run_shell("exit 3", None, "").is_err());
+    }
+
+    #[test]
+    fn spawned_shell_commands() {
+        let command = InputCommand::from(&UserCommandConfig {
+            name: "upper".into(),
+            run: "tr a-z A-Z".into(),
+            replace: true,
+            ..Default::default()
+        });
+        let (sender, receiver) = channel();
+        spawn_shell(&command, None, "abc", sender);
+        match receiver.recv().unwrap() {
+            AppEvent::ShellDone {
+                name,
+                replace,
+                result,
+            } => {
+                assert_eq!(name, "upper");
+                assert!(replace);
+                assert_eq!(result.unwrap(), "ABC");
+            }
+            _ => panic!("not ShellDone"),
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rime_api::{default_shared_data_dir, default_user_data_dir};
+use serde::{Deserialize, Serialize};
+
+use crate::glog::Severity;
+use crate::log_buffer::DEFAULT_LOG_LINES;
+use crate::log_file::LogFileOptions;
+
+/// Settings from the config file. Command line arguments override them.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub schema: Option<String>,
+    /// Rime shared data directory
+    pub shared_dir: String,
+    /// Rime user data directory
+    pub user_dir: String,
+    /// Prefix of input commands, like "/" in "/exit"
+    pub command_prefix: String,
+    /// Directory location for log files
+    pub log_dir: Option<String>,
+    /// Start a new log file once the current one reaches this many bytes
+    pub log_max_size: Option<u64>,
+    /// Keep at most this many log files in `log_dir`
+    pub log_keep_files: Option<usize>,
+    /// Remove log files older than this many days from `log_dir`
+    pub log_keep_days: Option<u64>,
+    /// Directory librime writes its own log files to, if the linked librime supports it
+    pub rime_log_dir: Option<String>,
+    /// Minimum severity of librime's log lines: "info", "warning", "error" or "fatal"
+    pub rime_log_level: Severity,
+    /// Also write rime-tui's own events (commands, schema switches, errors) and messages
+    /// to the log files
+    pub log_events: bool,
+    /// Maximum number of log lines kept in memory
+    pub log_lines: usize,
+    /// Minimum severity of the log lines shown: "info", "warning", "error" or "fatal"
+    pub log_level: Severity,
+    pub input_backend: InputBackend,
+    /// Keyboard device for the evdev input backend
+    pub evdev_device: Option<String>,
+    /// Other inputs running input commands, mapped to the command names,
+    /// like "/quit" = "exit". They may be followed by an argument too.
+    pub aliases: BTreeMap<String, String>,
+    /// Keys (in Rime key sequence notation, like "{Control+z}") mapped to input commands
+    /// (without the prefix, like "undo" or "toggle ascii_mode"). They only take effect when Rime doesn't handle the key,
+    /// and Return and Tab only when they don't run or complete a typed command.
+    pub key_bindings: BTreeMap<String, String>,
+    /// Key sequences toggling Rime options, for "toggle" and the status bar. They must be bound
+    /// in Rime's key_binder; the defaults are those of rime-prelude's key_bindings.yaml.
+    ///
+    /// This works around options not being settable directly: rime-api keeps the session id
+    /// librime's `set_option` needs private. So toggling only works with keys the schema binds.
+    pub option_keys: BTreeMap<String, String>,
+    pub theme: ThemeConfig,
+    pub layout: LayoutConfig,
+    pub commands: Vec<UserCommandConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            shared_dir: default_shared_data_dir().to_string_lossy().into(),
+            user_dir: default_user_data_dir().to_string_lossy().into(),
+            command_prefix: "/".into(),
+            log_dir: None,
+            log_max_size: None,
+            log_keep_files: None,
+            log_keep_days: None,
+            rime_log_dir: None,
+            rime_log_level: Severity::Info,
+            log_events: false,
+            log_lines: DEFAULT_LOG_LINES,
+            log_level: Severity::Info,
+            input_backend: InputBackend::X11,
+            evdev_device: None,
+            aliases: BTreeMap::new(),
+            key_bindings: BTreeMap::from([
+                ("{Control+z}".into(), "undo".into()),
+                ("{Control+Shift+Z}".into(), "redo".into()),
+                ("{F2}".into(), "schema".into()),
+                ("{F3}".into(), "candidates".into()),
+                ("{F9}".into(), "log".into()),
+                ("{F10}".into(), "maximize".into()),
+                ("{Shift+Page_Up}".into(), "scroll-log-up".into()),
+                ("{Shift+Page_Down}".into(), "scroll-log-down".into()),
+                ("{Control+Shift+L}".into(), "view-log".into()),
+                ("{F12}".into(), "severity".into()),
+                ("{F5}".into(), "toggle ascii_mode".into()),
+                ("{F6}".into(), "toggle full_shape".into()),
+                ("{F7}".into(), "toggle simplification".into()),
+                ("{F8}".into(), "toggle ascii_punct".into()),
+            ]),
+            option_keys: BTreeMap::from([
+                ("ascii_mode".into(), "{Control+Shift+2}".into()),
+                ("full_shape".into(), "{Control+Shift+3}".into()),
+                ("simplification".into(), "{Control+Shift+4}".into()),
+                ("ascii_punct".into(), "{Control+period}".into()),
+            ]),
+            theme: Default::default(),
+            layout: Default::default(),
+            commands: Vec::new(),
+        }
+    }
+}
+
+/// Where key events come from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputBackend {
+    /// XInput2, reading the keyboard while the terminal window is focused
+    #[default]
+    X11,
+    /// The terminal itself, through the kitty keyboard protocol
+    Terminal,
+    /// A Linux keyboard device, e.g. on the console
+    Evdev,
+}
+
+impl InputBackend {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "x11" => Some(Self::X11),
+            "terminal" => Some(Self::Terminal),
+            "evdev" => Some(Self::Evdev),
+            _ => None,
+        }
+    }
+}
+
+/// Colors, as names like "yellow" and "lightblue", or "#rrggbb"
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub preedit: String,
+    pub highlight_fg: String,
+    pub highlight_bg: String,
+    pub border: String,
+    pub log_warning: String,
+    pub log_error: String,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self {
+            preedit: "yellow".into(),
+            highlight_fg: "black".into(),
+            highlight_bg: "white".into(),
+            border: "reset".into(),
+            log_warning: "yellow".into(),
+            log_error: "red".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CandidateLayout {
+    /// A list in a pane beside the output
+    #[default]
+    Vertical,
+    /// A bar under the preedit
+    Horizontal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pane {
+    Preedit,
+    Candidates,
+    Output,
+    Log,
+    /// The status bar
+    Status,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    /// Margin around the whole UI
+    pub margin: u16,
+    /// Panes from top to bottom; panes left out are hidden.
+    ///
+    /// Adjacent Output and Log panes share their rows with the vertical candidate list.
+    pub panes: Vec<Pane>,
+    /// Side of the vertical candidate list
+    pub candidates_side: Side,
+    /// Width of the Candidates pane, in percent
+    pub candidates_width: u16,
+    /// Height of the Log pane, in percent
+    pub log_height: u16,
+    pub candidate_layout: CandidateLayout,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            margin: 2,
+            panes: vec![
+                Pane::Preedit,
+                Pane::Candidates,
+                Pane::Output,
+                Pane::Log,
+                Pane::Status,
+            ],
+            candidates_side: Side::Right,
+            candidates_width: 30,
+            log_height: 30,
+            candidate_layout: CandidateLayout::Vertical,
+        }
+    }
+}
+
+/// A user-defined input command, running a shell command. E.g.
+///
+/// ```toml
+/// [[commands]]
+/// name = "date"
+/// run = "date +%F"
+/// description = "Insert today's date"
+/// ```
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserCommandConfig {
+    pub name: String,
+    pub run: String,
+    pub description: String,
+    /// Replace the whole output with the command's stdout, instead of inserting it
+    pub replace: bool,
+    /// Take an argument, passed to the shell command as `$1`
+    pub argument: bool,
+}
+
+impl Config {
+    pub fn log_file_options(&self) -> LogFileOptions {
+        LogFileOptions {
+            max_size: self.log_max_size,
+            keep_files: self.log_keep_files,
+            keep_days: self.log_keep_days,
+            events: self.log_events,
+        }
+    }
+
+    /// Returns the default config if the file doesn't exist
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        toml::from_str(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("Config should be serializable")
+    }
+}
+
+/// `$XDG_CONFIG_HOME/rime-tui/config.toml`, or `~/.config/rime-tui/config.toml`
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d),
+        _ => home::home_dir()?.join(".config"),
+    };
+    Some(config_dir.join(crate::APP_NAME).join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_input_backend() {
+        let config: Config = toml::from_str(r#"input_backend = "evdev""#).unwrap();
+        assert_eq!(config.input_backend, InputBackend::Evdev);
+        assert!(toml::from_str::<Config>(r#"input_backend = "wayland""#).is_err());
+        assert_eq!(
+            InputBackend::from_name("terminal"),
+            Some(InputBackend::Terminal)
+        );
+        assert_eq!(InputBackend::from_name("X11"), None);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("input-backend = \"x11\"").is_err());
+        assert!(toml::from_str::<Config>("[layout]\ncandidates_witdh = 40").is_err());
+        assert!(toml::from_str::<Config>("[[commands]]\nname = \"a\"\ncmd = \"b\"").is_err());
+    }
+
+    #[test]
+    fn reads_the_printed_config() {
+        let config: Config = toml::from_str(&Config::default().to_toml()).unwrap();
+        assert_eq!(config.input_backend, InputBackend::X11);
+        assert_eq!(config.key_bindings, Config::default().key_bindings);
+    }
+}
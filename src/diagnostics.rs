@@ -0,0 +1,41 @@
+//! rime-tui's own messages, kept apart from librime's log lines on stderr
+
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+use crate::event::AppEvent;
+use crate::glog::{LogEntry, Severity};
+
+static SENDER: Lazy<Mutex<Option<Sender<AppEvent>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Sends the messages reported from now on to the main loop, as [`AppEvent::Message`]s
+pub fn set_sender(sender: Option<Sender<AppEvent>>) {
+    *SENDER.lock().unwrap() = sender;
+}
+
+/// Reports a message to the user.
+///
+/// Without a sender (like before the TUI starts), the message goes to stderr.
+pub fn report(severity: Severity, message: fmt::Arguments) {
+    let sender = SENDER.lock().unwrap();
+    match &*sender {
+        Some(s) => {
+            let _ = s.send(AppEvent::Message(LogEntry::own(
+                severity,
+                message.to_string(),
+            )));
+        }
+        None => eprintln!("{}", message),
+    }
+}
+
+pub fn info(message: fmt::Arguments) {
+    report(Severity::Info, message);
+}
+
+pub fn error(message: fmt::Arguments) {
+    report(Severity::Error, message);
+}
@@ -0,0 +1,338 @@
+use std::fs::{read_dir, File};
+use std::io;
+use std::io::Read;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+
+use libc::input_event;
+use librime_sys::{
+    RimeModifier_kAltMask, RimeModifier_kControlMask, RimeModifier_kLockMask,
+    RimeModifier_kReleaseMask, RimeModifier_kShiftMask, RimeModifier_kSuperMask,
+};
+use rime_api::KeyEvent;
+use x11::keysym::*;
+use x11::xlib;
+
+use crate::input::InputSource;
+use crate::key_event::qwerty_keysym;
+
+const EV_KEY: u16 = 0x01;
+
+const KEY_RELEASED: i32 = 0;
+const KEY_PRESSED: i32 = 1;
+const KEY_REPEATED: i32 = 2;
+
+/// X keycodes are evdev key codes shifted by 8
+const X_KEYCODE_OFFSET: u32 = 8;
+
+/// Reads key events from a Linux evdev device (`/dev/input/event*`).
+///
+/// This works on a bare VT and under Wayland, but it reads the keyboard
+/// regardless of which window is focused, and always uses the US-QWERTY layout.
+/// Its keys are dropped while the terminal reports it has lost the focus.
+/// The user needs read permission on the device (usually via the `input` group).
+pub struct EvdevInput {
+    device: File,
+    shift_pressed: bool,
+    ctrl_pressed: bool,
+    alt_pressed: bool,
+    super_pressed: bool,
+    caps_lock: bool,
+    num_lock: bool,
+}
+
+impl EvdevInput {
+    /// Opens `device`, or the first keyboard found by [`find_keyboard_device`] if it's `None`.
+    pub fn open(device: Option<&Path>) -> io::Result<Self> {
+        let path = match device {
+            Some(d) => d.to_path_buf(),
+            None => find_keyboard_device().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "No evdev keyboard device found")
+            })?,
+        };
+        let device = File::open(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to open {}: {}", path.display(), e),
+            )
+        })?;
+        Ok(Self {
+            device,
+            shift_pressed: false,
+            ctrl_pressed: false,
+            alt_pressed: false,
+            super_pressed: false,
+            caps_lock: false,
+            num_lock: false,
+        })
+    }
+}
+
+impl InputSource for EvdevInput {
+    /// Returns `Ok(None)` for non-key events.
+    ///
+    /// Focus is unknown to evdev, so [`InputSource::is_live`] is always true.
+    fn next_event(&mut self) -> io::Result<Option<KeyEvent>> {
+        let mut buf = [0_u8; size_of::<input_event>()];
+        self.device.read_exact(&mut buf)?;
+        let event = unsafe { (buf.as_ptr() as *const input_event).read_unaligned() };
+
+        if event.type_ != EV_KEY {
+            return Ok(None);
+        }
+        let pressed = match event.value {
+            KEY_PRESSED | KEY_REPEATED => true,
+            KEY_RELEASED => false,
+            _ => return Ok(None),
+        };
+
+        let mut effective = 0;
+        if self.caps_lock {
+            effective |= xlib::LockMask as i32;
+        }
+        if self.num_lock {
+            effective |= xlib::Mod2Mask as i32;
+        }
+        let code = qwerty_keysym(
+            event.code as u32 + X_KEYCODE_OFFSET,
+            self.shift_pressed,
+            effective,
+        );
+
+        let mut ke = KeyEvent {
+            key_code: code as i32,
+            modifiers: 0,
+        };
+        if !pressed {
+            ke.modifiers |= RimeModifier_kReleaseMask as i32;
+        }
+        if self.shift_pressed {
+            ke.modifiers |= RimeModifier_kShiftMask as i32;
+        }
+        if self.super_pressed {
+            ke.modifiers |= RimeModifier_kSuperMask as i32;
+        }
+        if self.ctrl_pressed {
+            ke.modifiers |= RimeModifier_kControlMask as i32;
+        }
+        if self.alt_pressed {
+            ke.modifiers |= RimeModifier_kAltMask as i32;
+        }
+        if self.caps_lock {
+            ke.modifiers |= RimeModifier_kLockMask as i32;
+        }
+
+        #[allow(non_upper_case_globals)]
+        match code {
+            XK_Control_L | XK_Control_R => self.ctrl_pressed = pressed,
+            XK_Shift_L | XK_Shift_R => self.shift_pressed = pressed,
+            XK_Alt_L | XK_Alt_R => self.alt_pressed = pressed,
+            XK_Super_L | XK_Super_R => self.super_pressed = pressed,
+            XK_Caps_Lock if event.value == KEY_PRESSED => self.caps_lock = !self.caps_lock,
+            XK_Num_Lock if event.value == KEY_PRESSED => self.num_lock = !self.num_lock,
+            _ => {}
+        }
+
+        Ok(Some(ke))
+    }
+}
+
+/// Finds a keyboard device via the `*-event-kbd` links udev creates
+pub fn find_keyboard_device() -> Option<PathBuf> {
+    ["/dev/input/by-path", "/dev/input/by-id"]
+        .iter()
+        .filter_map(|d| read_dir(d).ok())
+        .flat_map(|d| d.filter_map(Result::ok))
+        .map(|e| e.path())
+        .find(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.ends_with("-event-kbd"))
+                .unwrap_or(false)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use libc::{c_ulong, input_id, timeval, uinput_setup, UINPUT_MAX_NAME_SIZE};
+
+    use super::*;
+
+    const EV_SYN: u16 = 0x00;
+    const SYN_REPORT: u16 = 0;
+
+    const KEY_A: u16 = 30;
+    const KEY_LEFTSHIFT: u16 = 42;
+    const KEY_CAPSLOCK: u16 = 58;
+    const KEY_RIGHTMETA: u16 = 126;
+
+    const UI_SET_EVBIT: c_ulong = 0x4004_5564;
+    const UI_SET_KEYBIT: c_ulong = 0x4004_5565;
+    const UI_DEV_SETUP: c_ulong = 0x405c_5503;
+    const UI_DEV_CREATE: c_ulong = 0x5501;
+    const UI_DEV_DESTROY: c_ulong = 0x5502;
+
+    /// A virtual keyboard created through `/dev/uinput`, removed on drop
+    struct VirtualKeyboard {
+        uinput: File,
+        device: PathBuf,
+    }
+
+    impl VirtualKeyboard {
+        fn create(keys: &[u16]) -> io::Result<Self> {
+            let uinput = OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open("/dev/uinput")?;
+            let fd = uinput.as_raw_fd();
+            let name = format!("rime-tui test keyboard {}", std::process::id());
+            let mut setup = uinput_setup {
+                id: input_id {
+                    bustype: 0x03, // BUS_USB
+                    vendor: 0x1234,
+                    product: 0x5678,
+                    version: 1,
+                },
+                name: [0; UINPUT_MAX_NAME_SIZE],
+                ff_effects_max: 0,
+            };
+            for (dst, src) in setup.name.iter_mut().zip(name.bytes()) {
+                *dst = src as libc::c_char;
+            }
+            unsafe {
+                if libc::ioctl(fd, UI_SET_EVBIT, EV_KEY as libc::c_int) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                for &key in keys {
+                    if libc::ioctl(fd, UI_SET_KEYBIT, key as libc::c_int) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if libc::ioctl(fd, UI_DEV_SETUP, &setup as *const uinput_setup) < 0
+                    || libc::ioctl(fd, UI_DEV_CREATE) < 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            // udev needs a moment to create the device node
+            for _ in 0..50 {
+                if let Some(device) = find_device_node(&name) {
+                    return Ok(Self { uinput, device });
+                }
+                sleep(Duration::from_millis(20));
+            }
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "The uinput device node wasn't created",
+            ))
+        }
+
+        fn emit(&mut self, type_: u16, code: u16, value: i32) {
+            let event = input_event {
+                time: timeval {
+                    tv_sec: 0,
+                    tv_usec: 0,
+                },
+                type_,
+                code,
+                value,
+            };
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    &event as *const input_event as *const u8,
+                    size_of::<input_event>(),
+                )
+            };
+            self.uinput.write_all(bytes).unwrap();
+            if type_ != EV_SYN {
+                self.emit(EV_SYN, SYN_REPORT, 0);
+            }
+        }
+
+        fn tap(&mut self, key: u16) {
+            self.emit(EV_KEY, key, KEY_PRESSED);
+            self.emit(EV_KEY, key, KEY_RELEASED);
+        }
+    }
+
+    impl Drop for VirtualKeyboard {
+        fn drop(&mut self) {
+            unsafe {
+                libc::ioctl(self.uinput.as_raw_fd(), UI_DEV_DESTROY);
+            }
+        }
+    }
+
+    /// Finds `/dev/input/eventN` of the input device called `name`
+    fn find_device_node(name: &str) -> Option<PathBuf> {
+        let input = read_dir("/sys/devices/virtual/input")
+            .ok()?
+            .filter_map(Result::ok)
+            .find(|e| {
+                std::fs::read_to_string(e.path().join("name"))
+                    .map(|n| n.trim_end() == name)
+                    .unwrap_or(false)
+            })?;
+        let event = read_dir(input.path())
+            .ok()?
+            .filter_map(Result::ok)
+            .find(|e| e.file_name().to_string_lossy().starts_with("event"))?;
+        let node = Path::new("/dev/input").join(event.file_name());
+        node.exists().then_some(node)
+    }
+
+    fn next_key_event(input: &mut EvdevInput) -> (u32, i32) {
+        loop {
+            if let Some(ke) = input.next_event().unwrap() {
+                return (ke.key_code as u32, ke.modifiers);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs write access to /dev/uinput"]
+    fn reads_keys_from_uinput_device() {
+        let keys = [KEY_A, KEY_LEFTSHIFT, KEY_CAPSLOCK, KEY_RIGHTMETA];
+        let mut keyboard = VirtualKeyboard::create(&keys).expect("Can't create a uinput device");
+        let mut input = EvdevInput::open(Some(&keyboard.device)).unwrap();
+
+        let shift = RimeModifier_kShiftMask as i32;
+        let lock = RimeModifier_kLockMask as i32;
+        let super_ = RimeModifier_kSuperMask as i32;
+        let release = RimeModifier_kReleaseMask as i32;
+
+        keyboard.tap(KEY_A);
+        assert_eq!(next_key_event(&mut input), (XK_a, 0));
+        assert_eq!(next_key_event(&mut input), (XK_a, release));
+
+        keyboard.emit(EV_KEY, KEY_LEFTSHIFT, KEY_PRESSED);
+        keyboard.tap(KEY_A);
+        keyboard.emit(EV_KEY, KEY_LEFTSHIFT, KEY_RELEASED);
+        assert_eq!(next_key_event(&mut input), (XK_Shift_L, 0));
+        assert_eq!(next_key_event(&mut input), (XK_A, shift));
+        assert_eq!(next_key_event(&mut input), (XK_A, shift | release));
+        assert_eq!(next_key_event(&mut input), (XK_Shift_L, shift | release));
+
+        keyboard.tap(KEY_CAPSLOCK);
+        keyboard.tap(KEY_A);
+        assert_eq!(next_key_event(&mut input), (XK_Caps_Lock, 0));
+        assert_eq!(next_key_event(&mut input), (XK_Caps_Lock, lock | release));
+        assert_eq!(next_key_event(&mut input), (XK_A, lock));
+        assert_eq!(next_key_event(&mut input), (XK_A, lock | release));
+        keyboard.tap(KEY_CAPSLOCK);
+        next_key_event(&mut input);
+        next_key_event(&mut input);
+
+        keyboard.emit(EV_KEY, KEY_RIGHTMETA, KEY_PRESSED);
+        keyboard.emit(EV_KEY, KEY_RIGHTMETA, KEY_RELEASED);
+        assert_eq!(next_key_event(&mut input), (XK_Super_R, 0));
+        assert_eq!(next_key_event(&mut input), (XK_Super_R, super_ | release));
+    }
+}
@@ -0,0 +1,136 @@
+use std::io;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread::spawn;
+
+use crossterm::event::{
+    Event, KeyCode, KeyEvent as TermKeyEvent, KeyEventKind, KeyModifiers, MouseEvent,
+};
+use rime_api::KeyEvent;
+use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGTERM, SIGWINCH};
+use signal_hook::iterator::Signals;
+
+use crate::glog::LogEntry;
+use crate::input::InputSource;
+use crate::terminal_input::to_rime_key_event;
+
+/// Everything the main loop reacts to, gathered into one channel
+pub enum AppEvent {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+    /// The terminal was resized
+    Resize,
+    /// Whether key events are captured has changed
+    InputLive(bool),
+    /// The input source failed, and has stopped
+    InputError(io::Error),
+    /// A message of rime-tui itself, from [`crate::diagnostics::report`]
+    Message(LogEntry),
+    /// A shell command of a user-defined input command, run by
+    /// [`crate::command::spawn_shell`], has finished with this stdout
+    ShellDone {
+        name: String,
+        replace: bool,
+        result: io::Result<String>,
+    },
+    /// SIGINT, SIGTERM or SIGHUP was received, or Ctrl+C pressed in the terminal
+    /// while it isn't the input backend. The program should shut down.
+    Terminate(i32),
+}
+
+/// Runs the input source made by `open` on its own thread, sending its events to `sender`.
+///
+/// The source is created on that thread, so it doesn't need to be `Send`.
+pub fn spawn_input<I, F>(open: F, sender: Sender<AppEvent>)
+where
+    I: InputSource,
+    F: FnOnce() -> io::Result<I> + Send + 'static,
+{
+    spawn(move || {
+        let mut input = match open() {
+            Ok(i) => i,
+            Err(e) => {
+                let _ = sender.send(AppEvent::InputError(e));
+                return;
+            }
+        };
+        let mut live = input.is_live();
+        if sender.send(AppEvent::InputLive(live)).is_err() {
+            return;
+        }
+        loop {
+            let event = match input.next_event() {
+                Ok(e) => e,
+                Err(e) => {
+                    let _ = sender.send(AppEvent::InputError(e));
+                    return;
+                }
+            };
+            if input.is_live() != live {
+                live = input.is_live();
+                if sender.send(AppEvent::InputLive(live)).is_err() {
+                    return;
+                }
+            }
+            if let Some(ke) = event {
+                if sender.send(AppEvent::Key(ke)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Forwards the terminal event stream returned by [`crate::tui::TuiApp::start`].
+///
+/// Key events are only forwarded if `keys` is true, i.e. the terminal is the input backend.
+/// Focus changes are forwarded as [`AppEvent::InputLive`] if `focus` is true, for input
+/// backends that can't tell whether the terminal is focused.
+pub fn forward_terminal_events(
+    events: Receiver<Event>,
+    sender: Sender<AppEvent>,
+    keys: bool,
+    focus: bool,
+) {
+    spawn(move || {
+        for event in events {
+            let event = match event {
+                Event::Key(e) if keys => match to_rime_key_event(&e) {
+                    Some(ke) => AppEvent::Key(ke),
+                    None => continue,
+                },
+                // raw mode turns Ctrl+C into a key, rather than SIGINT
+                Event::Key(TermKeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => AppEvent::Terminate(SIGINT),
+                Event::Mouse(e) => AppEvent::Mouse(e),
+                Event::FocusGained if focus => AppEvent::InputLive(true),
+                Event::FocusLost if focus => AppEvent::InputLive(false),
+                Event::Resize(..) => AppEvent::Resize,
+                _ => continue,
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Forwards SIGINT, SIGTERM and SIGHUP as [`AppEvent::Terminate`], and SIGWINCH as [`AppEvent::Resize`]
+pub fn forward_signals(sender: Sender<AppEvent>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGWINCH])?;
+    spawn(move || {
+        for signal in signals.forever() {
+            let event = match signal {
+                SIGWINCH => AppEvent::Resize,
+                s => AppEvent::Terminate(s),
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! Parsing of the glog lines librime writes, like
+//! `W20231018 12:00:00.123456 1234 file.cc:42] message`
+
+use serde::{Deserialize, Serialize};
+
+use crate::log_file::EVENT_PREFIX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+    Fatal,
+}
+
+impl Severity {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            'I' => Some(Self::Info),
+            'W' => Some(Self::Warning),
+            'E' => Some(Self::Error),
+            'F' => Some(Self::Fatal),
+            _ => None,
+        }
+    }
+
+    /// Accepts names like "warning", "warn" and "w", ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "info" | "i" => Some(Self::Info),
+            "warning" | "warn" | "w" => Some(Self::Warning),
+            "error" | "e" => Some(Self::Error),
+            "fatal" | "f" => Some(Self::Fatal),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+            Self::Fatal => "fatal",
+        }
+    }
+
+    /// The next more severe level, wrapping around to `Info`
+    pub fn cycle(&self) -> Self {
+        match self {
+            Self::Info => Self::Warning,
+            Self::Warning => Self::Error,
+            Self::Error => Self::Fatal,
+            Self::Fatal => Self::Info,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub severity: Severity,
+    /// Like "20231018 12:00:00.123456"
+    pub timestamp: Option<String>,
+    /// Like "file.cc:42"
+    pub location: Option<String>,
+    pub message: String,
+    /// The whole line as captured
+    pub raw: String,
+}
+
+impl LogEntry {
+    /// Lines that aren't in the glog format are taken as continuations of the previous
+    /// entry, like the rest of a multi-line message, so they have its severity, `previous`.
+    pub fn parse(line: &str, previous: Severity) -> Self {
+        parse_glog(line).unwrap_or_else(|| Self {
+            severity: previous,
+            timestamp: None,
+            location: None,
+            message: line.into(),
+            raw: line.into(),
+        })
+    }
+
+    /// A message of rime-tui itself, marked with [`EVENT_PREFIX`]
+    pub fn own(severity: Severity, message: String) -> Self {
+        Self {
+            severity,
+            timestamp: None,
+            location: None,
+            raw: format!("{} {}", EVENT_PREFIX, message),
+            message,
+        }
+    }
+}
+
+fn parse_glog(line: &str) -> Option<LogEntry> {
+    let severity = Severity::from_char(line.chars().next()?)?;
+    let rest = &line[1..];
+
+    // old glog versions print no year
+    let (date, rest) = rest.split_once(' ')?;
+    if !matches!(date.len(), 4 | 8) || !date.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (time, rest) = rest.split_once(' ')?;
+    if !time
+        .bytes()
+        .all(|b| b.is_ascii_digit() || b == b':' || b == b'.')
+    {
+        return None;
+    }
+    // the thread ID is padded with spaces
+    let (thread_id, rest) = rest.trim_start().split_once(' ')?;
+    if !thread_id.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (location, message) = rest.split_once(']')?;
+
+    Some(LogEntry {
+        severity,
+        timestamp: Some(format!("{} {}", date, time)),
+        location: Some(location.into()),
+        message: message.strip_prefix(' ').unwrap_or(message).into(),
+        raw: line.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glog_lines() {
+        let entry = LogEntry::parse(
+            "W20231018 12:00:00.123456  1234 file.cc:42] a message] with brackets",
+            Severity::Info,
+        );
+        assert_eq!(entry.severity, Severity::Warning);
+        assert_eq!(entry.timestamp.as_deref(), Some("20231018 12:00:00.123456"));
+        assert_eq!(entry.location.as_deref(), Some("file.cc:42"));
+        assert_eq!(entry.message, "a message] with brackets");
+
+        // old glog versions print no year
+        let entry = LogEntry::parse(
+            "E1018 12:00:00.123456 1234 file.cc:42] oops",
+            Severity::Info,
+        );
+        assert_eq!(entry.severity, Severity::Error);
+        assert_eq!(entry.timestamp.as_deref(), Some("1018 12:00:00.123456"));
+    }
+
+    #[test]
+    fn continuation_lines() {
+        for line in [
+            "  the rest of a message",
+            "Info without a timestamp",
+            "W20231018 12:00:00 x] no thread ID",
+            "",
+        ] {
+            let entry = LogEntry::parse(line, Severity::Error);
+            assert_eq!(entry.severity, Severity::Error, "{:?}", line);
+            assert_eq!(entry.message, line);
+            assert_eq!(entry.raw, line);
+            assert_eq!(entry.location, None);
+        }
+    }
+
+    #[test]
+    fn own_messages() {
+        let entry = LogEntry::own(Severity::Warning, "careful".into());
+        assert_eq!(entry.raw, format!("{} careful", EVENT_PREFIX));
+        assert_eq!(entry.message, "careful");
+    }
+
+    #[test]
+    fn severity_names() {
+        assert_eq!(Severity::from_name("WARN"), Some(Severity::Warning));
+        assert_eq!(Severity::from_name("f"), Some(Severity::Fatal));
+        assert_eq!(Severity::from_name("debug"), None);
+        assert_eq!(Severity::Fatal.cycle(), Severity::Info);
+        assert!(Severity::Info < Severity::Warning);
+    }
+}
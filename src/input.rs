@@ -0,0 +1,111 @@
+use std::io;
+
+use rime_api::KeyEvent;
+
+/// A source of key events, decoupling the Rime processing from where the keys come from.
+///
+/// The yielded [`KeyEvent`]s carry X keysyms and Rime modifier masks (including
+/// the release mask), whatever the backend is.
+pub trait InputSource {
+    /// Blocks until the next event arrives. Returns `Ok(None)` if the event
+    /// is not a key event, or is dropped by the source.
+    fn next_event(&mut self) -> io::Result<Option<KeyEvent>>;
+
+    /// Whether key events are currently delivered
+    fn is_live(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::event::{spawn_input, AppEvent};
+
+    /// Replays `(live, result)` steps, then fails like a closed device
+    struct ScriptedInput {
+        steps: VecDeque<(bool, io::Result<Option<KeyEvent>>)>,
+        live: bool,
+    }
+
+    impl InputSource for ScriptedInput {
+        fn next_event(&mut self) -> io::Result<Option<KeyEvent>> {
+            let Some((live, result)) = self.steps.pop_front() else {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            };
+            self.live = live;
+            result
+        }
+
+        fn is_live(&self) -> bool {
+            self.live
+        }
+    }
+
+    fn key(key_code: i32) -> KeyEvent {
+        KeyEvent {
+            key_code,
+            modifiers: 0,
+        }
+    }
+
+    fn describe(event: AppEvent) -> String {
+        match event {
+            AppEvent::Key(ke) => format!("key {}", ke.key_code),
+            AppEvent::InputLive(live) => format!("live {}", live),
+            AppEvent::InputError(e) => format!("error {:?}", e.kind()),
+            _ => "other".into(),
+        }
+    }
+
+    fn run(input: io::Result<ScriptedInput>) -> Vec<String> {
+        let (sender, receiver) = channel();
+        spawn_input(move || input, sender);
+        receiver.into_iter().map(describe).collect()
+    }
+
+    #[test]
+    fn forwards_keys_and_liveness() {
+        let input = ScriptedInput {
+            steps: VecDeque::from([
+                (true, Ok(Some(key(0x61)))),
+                (true, Ok(None)),
+                (false, Ok(None)),
+                (false, Ok(None)),
+                (true, Ok(Some(key(0x62)))),
+            ]),
+            live: true,
+        };
+        assert_eq!(
+            run(Ok(input)),
+            [
+                "live true",
+                "key 97",
+                "live false",
+                "live true",
+                "key 98",
+                "error UnexpectedEof"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_open_errors() {
+        let input = Err(io::ErrorKind::PermissionDenied.into());
+        assert_eq!(run(input), ["error PermissionDenied"]);
+    }
+
+    #[test]
+    fn is_live_by_default() {
+        struct Silent;
+        impl InputSource for Silent {
+            fn next_event(&mut self) -> io::Result<Option<KeyEvent>> {
+                Ok(None)
+            }
+        }
+        assert!(Silent.is_live());
+    }
+}
@@ -1,3 +1,5 @@
+use std::os::raw::{c_uchar, c_uint};
+
 use librime_sys::{
     RimeModifier_kAltMask, RimeModifier_kControlMask, RimeModifier_kLockMask,
     RimeModifier_kReleaseMask, RimeModifier_kShiftMask, RimeModifier_kSuperMask,
@@ -6,16 +8,14 @@ use rime_api::KeyEvent;
 use x11::keysym::*;
 use x11::xinput2::XIDeviceEvent;
 use x11::xlib;
+use x11::xlib::{KeySym, XkbLookupKeySym};
 
-pub struct KeyEventResolver<F>
-where
-    F: Fn(KeyEvent),
-{
+/// Turns X11 key events into Rime key events, tracking the modifier keys state
+pub struct KeyEventResolver {
     shift_pressed: bool,
     ctrl_pressed: bool,
     alt_pressed: bool,
     super_pressed: bool,
-    callback: F,
 }
 
 const KEY_CTRL_L: u32 = 37;
@@ -24,24 +24,28 @@ const KEY_SHIFT_L: u32 = 50;
 const KEY_SHIFT_R: u32 = 62;
 const KEY_ALT_L: u32 = 64;
 const KEY_ALT_R: u32 = 108;
-const KEY_SUPER: u32 = 133;
+const KEY_SUPER_L: u32 = 133;
+const KEY_SUPER_R: u32 = 134;
+
+const NO_SYMBOL: KeySym = 0;
 
-impl<F> KeyEventResolver<F>
-where
-    F: Fn(KeyEvent),
-{
+impl KeyEventResolver {
     #[allow(clippy::new_without_default)]
-    pub fn new(callback: F) -> Self {
+    pub fn new() -> Self {
         Self {
             shift_pressed: false,
             ctrl_pressed: false,
             alt_pressed: false,
             super_pressed: false,
-            callback,
         }
     }
 
-    pub fn on_key_event(&mut self, event: &XIDeviceEvent) {
+    /// Forgets the modifier keys held, e.g. when their release may not be seen
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn resolve(&mut self, event: &XIDeviceEvent) -> KeyEvent {
         let detail = event.detail as u32;
         let effective = event.mods.effective;
 
@@ -49,98 +53,12 @@ where
 
         let mut ke = KeyEvent::new(0, 0);
 
-        // symbols will change when Shift is pressed, e.g. XK_2 -> XK_at
+        // the keymap picks the level from the modifiers, e.g. XK_2 -> XK_at with Shift,
+        // and the fallback table only knows about Shift
         let shift_pressed = self.shift_pressed;
-        let code = match detail {
-            d @ 24..=33 if shift_pressed => {
-                [XK_Q, XK_W, XK_E, XK_R, XK_T, XK_Y, XK_U, XK_I, XK_O, XK_P][(d - 24) as usize]
-            }
-            d @ 38..=46 if shift_pressed => {
-                [XK_A, XK_S, XK_D, XK_F, XK_G, XK_H, XK_J, XK_K, XK_L][(d - 38) as usize]
-            }
-            d @ 52..=58 if shift_pressed => {
-                [XK_Z, XK_X, XK_C, XK_V, XK_B, XK_N, XK_M][(d - 52) as usize]
-            }
-            d @ 10..=19 if shift_pressed => [
-                XK_exclam,
-                XK_at,
-                XK_numbersign,
-                XK_dollar,
-                XK_percent,
-                XK_asciicircum,
-                XK_ampersand,
-                XK_asterisk,
-                XK_parenleft,
-                XK_parenright,
-            ][(d - 10) as usize],
-            d @ 24..=33 => {
-                [XK_q, XK_w, XK_e, XK_r, XK_t, XK_y, XK_u, XK_i, XK_o, XK_p][(d - 24) as usize]
-            }
-            d @ 38..=46 => {
-                [XK_a, XK_s, XK_d, XK_f, XK_g, XK_h, XK_j, XK_k, XK_l][(d - 38) as usize]
-            }
-            d @ 52..=58 => [XK_z, XK_x, XK_c, XK_v, XK_b, XK_n, XK_m][(d - 52) as usize],
-            65 => XK_space,
-            22 => XK_BackSpace,
-            36 => XK_Return,
-            9 => XK_Escape,
-            20 if shift_pressed => XK_underscore,
-            20 => XK_minus,
-            21 if shift_pressed => XK_plus,
-            21 => XK_equal,
-            34 if shift_pressed => XK_braceleft,
-            34 => XK_bracketleft,
-            35 if shift_pressed => XK_braceright,
-            35 => XK_bracketright,
-            51 if shift_pressed => XK_bar,
-            51 => XK_backslash,
-            47 if shift_pressed => XK_colon,
-            47 => XK_semicolon,
-            48 if shift_pressed => XK_quotedbl,
-            48 => XK_apostrophe,
-            59 if shift_pressed => XK_less,
-            59 => XK_comma,
-            60 if shift_pressed => XK_greater,
-            60 => XK_period,
-            61 if shift_pressed => XK_question,
-            61 => XK_slash,
-            d @ 10..=19 => {
-                [XK_1, XK_2, XK_3, XK_4, XK_5, XK_6, XK_7, XK_8, XK_9, XK_0][(d - 10) as usize]
-            }
-            111 => XK_Up,
-            116 => XK_Down,
-            113 => XK_Left,
-            114 => XK_Right,
-            23 => XK_Tab,
-            66 => XK_Caps_Lock,
-            49 if shift_pressed => XK_asciitilde,
-            49 => XK_grave,
-            d @ 87..=89 if effective & 0x10 != 0x10 /* not in NumLK */ => [XK_KP_1, XK_KP_2, XK_KP_3][(d - 87) as usize],
-            d @ 83..=85 if effective & 0x10 != 0x10 => [XK_KP_4, XK_KP_5, XK_KP_6][(d - 83) as usize],
-            d @ 79..=81 if effective & 0x10 != 0x10 => [XK_KP_7, XK_KP_8, XK_KP_9][(d - 79) as usize],
-            90 if effective & 0x10 != 0x10 => XK_KP_0,
-            91 => XK_KP_Delete,
-            104 => XK_KP_Enter,
-            82 => XK_KP_Subtract,
-            86 => XK_KP_Add,
-            77 => XK_Num_Lock,
-            106 => XK_KP_Divide,
-            63 => XK_KP_Multiply,
-            d@67..=76 => [XK_F1,XK_F2,XK_F3,XK_F4,XK_F5,XK_F6,XK_F7,XK_F8,XK_F9,XK_F10][(d - 67) as usize],
-            95 => XK_F11,
-            96 => XK_F12,
-            127 => XK_Pause,
-            119 => XK_Delete,
-            KEY_ALT_L => XK_Alt_L,
-            KEY_ALT_R => XK_Alt_R,
-            KEY_SHIFT_L => XK_Shift_L,
-            KEY_SHIFT_R => XK_Shift_R,
-            KEY_CTRL_L => XK_Control_L,
-            KEY_CTRL_R => XK_Control_R,
-            _ => {
-                0xffffff /* Void symbol */
-            }
-        } as i32;
+        let code = lookup_keysym(event)
+            .unwrap_or_else(|| qwerty_keysym(detail, shift_pressed, effective))
+            as i32;
         ke.key_code = code;
         if release {
             ke.modifiers |= RimeModifier_kReleaseMask as i32;
@@ -157,31 +75,255 @@ where
         if self.alt_pressed {
             ke.modifiers |= RimeModifier_kAltMask as i32;
         }
-        if effective & 0x2 == 0x2 {
+        if effective & xlib::LockMask as i32 != 0 {
             ke.modifiers |= RimeModifier_kLockMask as i32;
         }
-        (self.callback)(ke);
-
-        match event.evtype {
-            xlib::KeyPress => match detail {
-                KEY_CTRL_L | KEY_CTRL_R => self.ctrl_pressed = true,
-                KEY_SHIFT_L | KEY_SHIFT_R => self.shift_pressed = true,
-                KEY_ALT_L | KEY_ALT_R => self.alt_pressed = true,
-                KEY_SUPER => self.super_pressed = true,
-                _ => {}
-            },
-            xlib::KeyRelease => match detail {
-                KEY_CTRL_L | KEY_CTRL_R => self.ctrl_pressed = false,
-                KEY_SHIFT_L | KEY_SHIFT_R => self.shift_pressed = false,
-                KEY_ALT_L | KEY_ALT_R => self.alt_pressed = false,
-                KEY_SUPER => self.super_pressed = false,
-                _ => {}
-            },
-            _ => {}
+        let pressed = match event.evtype {
+            xlib::KeyPress => true,
+            xlib::KeyRelease => false,
+            _ => return ke,
         };
+        #[allow(non_upper_case_globals)]
+        match code as u32 {
+            XK_Control_L | XK_Control_R => self.ctrl_pressed = pressed,
+            XK_Shift_L | XK_Shift_R => self.shift_pressed = pressed,
+            XK_Alt_L | XK_Alt_R | XK_Meta_L | XK_Meta_R => self.alt_pressed = pressed,
+            XK_Super_L | XK_Super_R => self.super_pressed = pressed,
+            _ => {}
+        }
+        ke
+    }
+}
+
+/// Asks the X server's XKB keymap for the keysym of the key in `event`, in the active
+/// layout group and at the level its modifiers select (Shift, CapsLock, NumLock, AltGr, etc.).
+///
+/// Returns `None` if the keymap has no symbol for this key.
+fn lookup_keysym(event: &XIDeviceEvent) -> Option<u32> {
+    let display = event.display;
+    if display.is_null() {
+        return None;
+    }
+    let keycode = event.detail as c_uchar;
+    // like `XkbBuildCoreState`: the group goes into bits 13 and 14
+    let state =
+        ((event.group.effective as c_uint & 0x3) << 13) | (event.mods.effective as c_uint & 0xff);
+    let mut consumed_mods = 0;
+    let mut keysym = NO_SYMBOL;
+    let found =
+        unsafe { XkbLookupKeySym(display, keycode, state, &mut consumed_mods, &mut keysym) };
+    if found == 0 || keysym == NO_SYMBOL {
+        return None;
+    }
+    Some(keysym as u32)
+}
+
+/// Keysym table for a US-QWERTY keyboard, indexed by X keycode (evdev key code + 8).
+/// `effective` is the X modifier mask, where only CapsLock and NumLock are looked at.
+///
+/// The X11 backend only uses this when the XKB keymap can't be queried.
+pub fn qwerty_keysym(detail: u32, shift_pressed: bool, effective: i32) -> u32 {
+    // CapsLock only affects letters, and Shift turns them back to lowercase
+    let upper = shift_pressed != (effective & xlib::LockMask as i32 != 0);
+    match detail {
+        d @ 24..=33 if upper => {
+            [XK_Q, XK_W, XK_E, XK_R, XK_T, XK_Y, XK_U, XK_I, XK_O, XK_P][(d - 24) as usize]
+        }
+        d @ 38..=46 if upper => {
+            [XK_A, XK_S, XK_D, XK_F, XK_G, XK_H, XK_J, XK_K, XK_L][(d - 38) as usize]
+        }
+        d @ 52..=58 if upper => {
+            [XK_Z, XK_X, XK_C, XK_V, XK_B, XK_N, XK_M][(d - 52) as usize]
+        }
+        d @ 10..=19 if shift_pressed => [
+            XK_exclam,
+            XK_at,
+            XK_numbersign,
+            XK_dollar,
+            XK_percent,
+            XK_asciicircum,
+            XK_ampersand,
+            XK_asterisk,
+            XK_parenleft,
+            XK_parenright,
+        ][(d - 10) as usize],
+        d @ 24..=33 => {
+            [XK_q, XK_w, XK_e, XK_r, XK_t, XK_y, XK_u, XK_i, XK_o, XK_p][(d - 24) as usize]
+        }
+        d @ 38..=46 => {
+            [XK_a, XK_s, XK_d, XK_f, XK_g, XK_h, XK_j, XK_k, XK_l][(d - 38) as usize]
+        }
+        d @ 52..=58 => [XK_z, XK_x, XK_c, XK_v, XK_b, XK_n, XK_m][(d - 52) as usize],
+        65 => XK_space,
+        22 => XK_BackSpace,
+        36 => XK_Return,
+        9 => XK_Escape,
+        20 if shift_pressed => XK_underscore,
+        20 => XK_minus,
+        21 if shift_pressed => XK_plus,
+        21 => XK_equal,
+        34 if shift_pressed => XK_braceleft,
+        34 => XK_bracketleft,
+        35 if shift_pressed => XK_braceright,
+        35 => XK_bracketright,
+        51 if shift_pressed => XK_bar,
+        51 => XK_backslash,
+        47 if shift_pressed => XK_colon,
+        47 => XK_semicolon,
+        48 if shift_pressed => XK_quotedbl,
+        48 => XK_apostrophe,
+        59 if shift_pressed => XK_less,
+        59 => XK_comma,
+        60 if shift_pressed => XK_greater,
+        60 => XK_period,
+        61 if shift_pressed => XK_question,
+        61 => XK_slash,
+        d @ 10..=19 => {
+            [XK_1, XK_2, XK_3, XK_4, XK_5, XK_6, XK_7, XK_8, XK_9, XK_0][(d - 10) as usize]
+        }
+        111 => XK_Up,
+        116 => XK_Down,
+        113 => XK_Left,
+        114 => XK_Right,
+        23 => XK_Tab,
+        66 => XK_Caps_Lock,
+        49 if shift_pressed => XK_asciitilde,
+        49 => XK_grave,
+        d @ 87..=89 if effective & 0x10 != 0x10 /* not in NumLK */ => [XK_KP_1, XK_KP_2, XK_KP_3][(d - 87) as usize],
+        d @ 83..=85 if effective & 0x10 != 0x10 => [XK_KP_4, XK_KP_5, XK_KP_6][(d - 83) as usize],
+        d @ 79..=81 if effective & 0x10 != 0x10 => [XK_KP_7, XK_KP_8, XK_KP_9][(d - 79) as usize],
+        90 if effective & 0x10 != 0x10 => XK_KP_0,
+        91 => XK_KP_Delete,
+        104 => XK_KP_Enter,
+        82 => XK_KP_Subtract,
+        86 => XK_KP_Add,
+        77 => XK_Num_Lock,
+        106 => XK_KP_Divide,
+        63 => XK_KP_Multiply,
+        d@67..=76 => [XK_F1,XK_F2,XK_F3,XK_F4,XK_F5,XK_F6,XK_F7,XK_F8,XK_F9,XK_F10][(d - 67) as usize],
+        95 => XK_F11,
+        96 => XK_F12,
+        127 => XK_Pause,
+        119 => XK_Delete,
+        118 => XK_Insert,
+        110 => XK_Home,
+        115 => XK_End,
+        112 => XK_Page_Up,
+        117 => XK_Page_Down,
+        KEY_ALT_L => XK_Alt_L,
+        KEY_ALT_R => XK_Alt_R,
+        KEY_SHIFT_L => XK_Shift_L,
+        KEY_SHIFT_R => XK_Shift_R,
+        KEY_CTRL_L => XK_Control_L,
+        KEY_CTRL_R => XK_Control_R,
+        KEY_SUPER_L => XK_Super_L,
+        KEY_SUPER_R => XK_Super_R,
+        _ => {
+            0xffffff /* Void symbol */
+        }
     }
 }
 
 pub fn wrap(key: &str) -> String {
     format!("{{{}}}", key)
 }
+
+/// Latin-1 characters have keysyms of the same value; other Unicode
+/// characters are mapped to `0x01000000 + code point`.
+pub fn char_to_keysym(c: char) -> u32 {
+    match c as u32 {
+        c @ (0x20..=0x7e | 0xa0..=0xff) => c,
+        c => 0x0100_0000 + c,
+    }
+}
+
+/// The inverse of [`char_to_keysym`]; returns `None` for keysyms of non-printable keys
+pub fn keysym_to_char(keysym: u32) -> Option<char> {
+    match keysym {
+        k @ (0x20..=0x7e | 0xa0..=0xff) => char::from_u32(k),
+        k @ 0x0100_0000..=0x0110_ffff => char::from_u32(k - 0x0100_0000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: i32 = xlib::LockMask as i32;
+    const NUM_LOCK: i32 = 0x10;
+
+    #[test]
+    fn qwerty_letters() {
+        assert_eq!(qwerty_keysym(24, false, 0), XK_q);
+        assert_eq!(qwerty_keysym(46, false, 0), XK_l);
+        assert_eq!(qwerty_keysym(58, false, 0), XK_m);
+        assert_eq!(qwerty_keysym(24, true, 0), XK_Q);
+        // CapsLock uppercases letters, and Shift turns them back
+        assert_eq!(qwerty_keysym(38, false, LOCK), XK_A);
+        assert_eq!(qwerty_keysym(38, true, LOCK), XK_a);
+    }
+
+    #[test]
+    fn qwerty_shifted_symbols() {
+        assert_eq!(qwerty_keysym(10, false, 0), XK_1);
+        assert_eq!(qwerty_keysym(10, true, 0), XK_exclam);
+        assert_eq!(qwerty_keysym(11, true, 0), XK_at);
+        assert_eq!(qwerty_keysym(19, true, 0), XK_parenright);
+        assert_eq!(qwerty_keysym(20, true, 0), XK_underscore);
+        assert_eq!(qwerty_keysym(47, true, 0), XK_colon);
+        assert_eq!(qwerty_keysym(61, true, 0), XK_question);
+        // CapsLock doesn't shift digits or symbols
+        assert_eq!(qwerty_keysym(10, false, LOCK), XK_1);
+        assert_eq!(qwerty_keysym(60, false, LOCK), XK_period);
+    }
+
+    #[test]
+    fn qwerty_other_keys() {
+        assert_eq!(qwerty_keysym(65, false, 0), XK_space);
+        assert_eq!(qwerty_keysym(36, false, 0), XK_Return);
+        assert_eq!(qwerty_keysym(67, false, 0), XK_F1);
+        assert_eq!(qwerty_keysym(96, false, 0), XK_F12);
+        assert_eq!(qwerty_keysym(KEY_SUPER_R, false, 0), XK_Super_R);
+        // the keypad digits only without NumLock
+        assert_eq!(qwerty_keysym(87, false, 0), XK_KP_1);
+        assert_eq!(qwerty_keysym(87, false, NUM_LOCK), 0xffffff);
+    }
+
+    #[test]
+    fn qwerty_unknown_keycode() {
+        assert_eq!(qwerty_keysym(0, false, 0), 0xffffff);
+        assert_eq!(qwerty_keysym(255, true, LOCK), 0xffffff);
+    }
+
+    #[test]
+    fn chars_and_keysyms() {
+        for (c, keysym) in [
+            ('a', XK_a),
+            ('Z', XK_Z),
+            (' ', XK_space),
+            ('~', XK_asciitilde),
+            ('é', XK_eacute),
+            ('你', 0x0100_4f60),
+            ('€', 0x0100_20ac),
+        ] {
+            assert_eq!(char_to_keysym(c), keysym);
+            assert_eq!(keysym_to_char(keysym), Some(c));
+        }
+    }
+
+    #[test]
+    fn keysyms_of_non_printable_keys() {
+        for keysym in [
+            XK_Return,
+            XK_BackSpace,
+            XK_F1,
+            XK_Shift_L,
+            0x1f,
+            0x7f,
+            0xffffff,
+        ] {
+            assert_eq!(keysym_to_char(keysym), None);
+        }
+    }
+}
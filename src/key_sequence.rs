@@ -0,0 +1,159 @@
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+
+use librime_sys::{
+    RimeModifier_kAltMask, RimeModifier_kControlMask, RimeModifier_kLockMask,
+    RimeModifier_kReleaseMask, RimeModifier_kShiftMask, RimeModifier_kSuperMask,
+};
+use rime_api::KeyEvent;
+use x11::xlib::{KeySym, XStringToKeysym};
+
+use crate::key_event::char_to_keysym;
+
+#[derive(Debug)]
+pub struct ParseError(String);
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid key sequence: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses Rime's key sequence notation, like `nihao{space}{Control+Return}`.
+///
+/// Plain characters are keys without modifiers; a `{...}` group is an X keysym name,
+/// optionally prefixed by `Shift+`, `Control+`, `Alt+`, `Super+`, `Lock+` or `Release+`.
+pub fn parse_key_sequence(sequence: &str) -> Result<Vec<KeyEvent>, ParseError> {
+    let mut events = Vec::new();
+    let mut chars = sequence.chars();
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            events.push(KeyEvent {
+                key_code: char_to_keysym(c) as i32,
+                modifiers: 0,
+            });
+            continue;
+        }
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some('}') if !name.is_empty() => break,
+                Some(c) => name.push(c),
+                None => return Err(ParseError(format!("unclosed '{{' in \"{}\"", sequence))),
+            }
+        }
+        events.push(parse_key(&name)?);
+    }
+    Ok(events)
+}
+
+fn parse_key(key: &str) -> Result<KeyEvent, ParseError> {
+    let mut modifiers = 0_u32;
+    let mut name = key;
+    // "+" itself may be the key name, as in `{Shift++}`
+    while let Some((modifier, rest)) = name.split_once('+').filter(|(_, r)| !r.is_empty()) {
+        modifiers |= match modifier {
+            "Shift" => RimeModifier_kShiftMask,
+            "Control" => RimeModifier_kControlMask,
+            "Alt" => RimeModifier_kAltMask,
+            "Super" => RimeModifier_kSuperMask,
+            "Lock" => RimeModifier_kLockMask,
+            "Release" => RimeModifier_kReleaseMask,
+            _ => return Err(ParseError(format!("unknown modifier \"{}\"", modifier))),
+        };
+        name = rest;
+    }
+
+    let keysym = match name.chars().count() {
+        1 => char_to_keysym(name.chars().next().unwrap()) as KeySym,
+        _ => {
+            let c_name = CString::new(name)
+                .map_err(|_| ParseError(format!("invalid key name \"{}\"", name)))?;
+            unsafe { XStringToKeysym(c_name.as_ptr()) }
+        }
+    };
+    if keysym == 0 {
+        return Err(ParseError(format!("unknown key \"{}\"", name)));
+    }
+    Ok(KeyEvent {
+        key_code: keysym as i32,
+        modifiers: modifiers as i32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use x11::keysym::*;
+
+    use super::*;
+
+    fn parse(sequence: &str) -> Vec<(u32, u32)> {
+        parse_key_sequence(sequence)
+            .unwrap()
+            .iter()
+            .map(|ke| (ke.key_code as u32, ke.modifiers as u32))
+            .collect()
+    }
+
+    #[test]
+    fn plain_characters() {
+        assert_eq!(
+            parse("ni hao"),
+            [
+                (XK_n, 0),
+                (XK_i, 0),
+                (XK_space, 0),
+                (XK_h, 0),
+                (XK_a, 0),
+                (XK_o, 0),
+            ]
+        );
+        assert_eq!(parse("你"), [(0x0100_4f60, 0)]);
+        assert_eq!(parse(""), []);
+    }
+
+    #[test]
+    fn named_keys_with_modifiers() {
+        assert_eq!(
+            parse("a{space}{Control+Return}{Shift+Alt+Left}"),
+            [
+                (XK_a, 0),
+                (XK_space, 0),
+                (XK_Return, RimeModifier_kControlMask),
+                (XK_Left, RimeModifier_kShiftMask | RimeModifier_kAltMask),
+            ]
+        );
+        assert_eq!(
+            parse("{Release+Super+x}{Lock+A}"),
+            [
+                (XK_x, RimeModifier_kReleaseMask | RimeModifier_kSuperMask),
+                (XK_A, RimeModifier_kLockMask),
+            ]
+        );
+    }
+
+    #[test]
+    fn plus_and_braces_as_keys() {
+        assert_eq!(
+            parse("{+}{Shift++}{}}"),
+            [
+                (XK_plus, 0),
+                (XK_plus, RimeModifier_kShiftMask),
+                (XK_braceright, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_sequences() {
+        for sequence in ["{space", "{}", "{Hyper+a}", "{NoSuchKey}", "{Control+}"] {
+            assert!(
+                parse_key_sequence(sequence).is_err(),
+                "\"{}\" should be invalid",
+                sequence
+            );
+        }
+    }
+}
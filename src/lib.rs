@@ -1,11 +1,29 @@
-use std::mem;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
 use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::time::Duration;
+use std::{fs, io, mem};
 use x11_clipboard::Clipboard;
 
 pub mod cli;
+pub mod command;
+pub mod config;
+pub mod diagnostics;
+pub mod evdev_input;
+pub mod event;
 pub mod fd_reader;
+pub mod glog;
+pub mod input;
 pub mod key_event;
+pub mod key_sequence;
+pub mod log_buffer;
+pub mod log_file;
+pub mod recovery;
+pub mod rime_ext;
+pub mod session;
+pub mod terminal_input;
+pub mod text_buffer;
 pub mod tui;
 pub mod xinput;
 
@@ -59,6 +77,24 @@ pub fn load_clipboard() -> Result<String, ClipboardError> {
     }
 }
 
+pub fn save_file(path: &Path, text: &str) -> io::Result<()> {
+    fs::write(path, text)
+}
+
+pub fn append_file(path: &Path, text: &str) -> io::Result<()> {
+    let mut file = File::options().append(true).create(true).open(path)?;
+    file.write_all(text.as_bytes())
+}
+
+pub fn open_file(path: &Path) -> io::Result<String> {
+    fs::read_to_string(path)
+}
+
+/// Keeps the recovery files of the sessions, in the user data directory
+pub const RECOVERY_DIR_NAME: &str = "rime-tui.recovery.d";
+/// Keeps the schema last chosen in the schema picker, in the user data directory
+pub const LAST_SCHEMA_FILE_NAME: &str = "rime-tui.last_schema";
+
 pub const DISTRIBUTION_NAME: &str = "Rime";
 pub const DISTRIBUTION_CODE_NAME: &str = "Rime";
 pub const DISTRIBUTION_VERSION: &str = "0.0.0";
@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+
+use crate::glog::{LogEntry, Severity};
+
+/// Default of [`crate::config::Config::log_lines`]
+pub const DEFAULT_LOG_LINES: usize = 10000;
+
+/// The captured log entries, keeping only the latest `capacity` ones.
+///
+/// Entries below the minimum severity are kept, but not visible. The visible ones are indexed
+/// as they're pushed, so they're counted and looked up without filtering on every redraw.
+#[derive(Debug)]
+pub struct LogBuffer {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+    min_severity: Severity,
+    /// Sequence number of the front entry; every entry pushed gets the next one
+    front_seq: u64,
+    /// Sequence numbers of the visible entries, from the oldest
+    visible: VecDeque<u64>,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOG_LINES)
+    }
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+            min_severity: Severity::Info,
+            front_seq: 0,
+            visible: VecDeque::new(),
+        }
+    }
+
+    /// Drops the oldest entry if full. Returns whether a visible entry was dropped.
+    pub fn push(&mut self, entry: LogEntry) -> bool {
+        let mut dropped = false;
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            if self.visible.front() == Some(&self.front_seq) {
+                self.visible.pop_front();
+                dropped = true;
+            }
+            self.front_seq += 1;
+        }
+        if self.is_visible(&entry) {
+            self.visible
+                .push_back(self.front_seq + self.entries.len() as u64);
+        }
+        self.entries.push_back(entry);
+        dropped
+    }
+
+    pub fn min_severity(&self) -> Severity {
+        self.min_severity
+    }
+
+    pub fn set_min_severity(&mut self, severity: Severity) {
+        self.min_severity = severity;
+        self.visible = (self.front_seq..)
+            .zip(&self.entries)
+            .filter(|(_, e)| e.severity >= severity)
+            .map(|(seq, _)| seq)
+            .collect();
+    }
+
+    pub fn is_visible(&self, entry: &LogEntry) -> bool {
+        entry.severity >= self.min_severity
+    }
+
+    /// The `index`-th visible entry, from the oldest.
+    /// Indices elsewhere refer to the visible entries too.
+    pub fn get(&self, index: usize) -> Option<&LogEntry> {
+        let seq = self.visible.get(index)?;
+        self.entries.get((seq - self.front_seq) as usize)
+    }
+
+    /// Number of visible entries
+    pub fn len(&self) -> usize {
+        self.visible.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.visible.is_empty()
+    }
+
+    /// Index of the last visible entry before `before` containing `query`, ignoring case
+    pub fn rfind(&self, query: &str, before: usize) -> Option<usize> {
+        let query = query.to_lowercase();
+        (0..before.min(self.len()))
+            .rev()
+            .find(|&i| self.matches(i, &query))
+    }
+
+    /// Index of the first visible entry after `after` containing `query`, ignoring case
+    pub fn find(&self, query: &str, after: usize) -> Option<usize> {
+        let query = query.to_lowercase();
+        (after + 1..self.len()).find(|&i| self.matches(i, &query))
+    }
+
+    /// Whether the `index`-th visible entry contains `query`, which is in lowercase
+    fn matches(&self, index: usize, query: &str) -> bool {
+        self.get(index)
+            .is_some_and(|e| e.raw.to_lowercase().contains(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(severity: Severity, message: &str) -> LogEntry {
+        LogEntry::own(severity, message.into())
+    }
+
+    fn messages(log: &LogBuffer) -> Vec<&str> {
+        (0..log.len())
+            .map(|i| log.get(i).unwrap().message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn filter_by_severity() {
+        let mut log = LogBuffer::new(10);
+        log.push(entry(Severity::Info, "a"));
+        log.push(entry(Severity::Warning, "b"));
+        log.push(entry(Severity::Error, "c"));
+        assert_eq!(messages(&log), ["a", "b", "c"]);
+
+        log.set_min_severity(Severity::Warning);
+        assert_eq!(messages(&log), ["b", "c"]);
+        log.push(entry(Severity::Info, "d"));
+        log.push(entry(Severity::Fatal, "e"));
+        assert_eq!(messages(&log), ["b", "c", "e"]);
+        assert!(log.get(3).is_none());
+
+        log.set_min_severity(Severity::Fatal);
+        assert_eq!(messages(&log), ["e"]);
+        log.set_min_severity(Severity::Info);
+        assert_eq!(messages(&log), ["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn hidden_entries_are_kept_and_dropped() {
+        let mut log = LogBuffer::new(3);
+        log.set_min_severity(Severity::Warning);
+        assert!(!log.push(entry(Severity::Info, "a")));
+        assert!(!log.push(entry(Severity::Warning, "b")));
+        assert!(!log.push(entry(Severity::Info, "c")));
+        assert!(log.is_visible(&entry(Severity::Error, "")));
+        // "a" is dropped, but isn't visible
+        assert!(!log.push(entry(Severity::Error, "d")));
+        assert_eq!(messages(&log), ["b", "d"]);
+        // "b" is
+        assert!(log.push(entry(Severity::Info, "e")));
+        assert_eq!(messages(&log), ["d"]);
+
+        log.set_min_severity(Severity::Info);
+        assert_eq!(messages(&log), ["c", "d", "e"]);
+    }
+
+    #[test]
+    fn keeps_the_latest_entries() {
+        let mut log = LogBuffer::new(2);
+        assert!(log.is_empty());
+        assert!(!log.push(entry(Severity::Info, "a")));
+        assert!(!log.push(entry(Severity::Info, "b")));
+        assert!(log.push(entry(Severity::Info, "c")));
+        assert_eq!(messages(&log), ["b", "c"]);
+        assert_eq!(LogBuffer::new(0).capacity, 1);
+    }
+
+    #[test]
+    fn search() {
+        let mut log = LogBuffer::new(10);
+        for message in ["Foo", "bar", "foo bar", "baz"] {
+            log.push(entry(Severity::Info, message));
+        }
+        assert_eq!(log.rfind("FOO", 4), Some(2));
+        assert_eq!(log.rfind("foo", 2), Some(0));
+        assert_eq!(log.rfind("foo", 0), None);
+        assert_eq!(log.rfind("foo", 100), Some(2));
+        assert_eq!(log.find("bar", 0), Some(1));
+        assert_eq!(log.find("bar", 1), Some(2));
+        assert_eq!(log.find("bar", 2), None);
+        assert_eq!(log.find("qux", 0), None);
+
+        // indices are of the visible entries
+        log.push(entry(Severity::Warning, "bar again"));
+        log.set_min_severity(Severity::Warning);
+        assert_eq!(log.rfind("bar", 1), Some(0));
+        assert_eq!(log.find("bar", 0), None);
+    }
+}
@@ -0,0 +1,262 @@
+//! Log files in `--log-dir`, rotated by size and cleaned up by count and age
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Prefix of rime-tui's own messages and events, to tell them from librime's output
+pub const EVENT_PREFIX: &str = "[rime-tui]";
+
+static LOG_FILE: Lazy<Mutex<Option<LogFile>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Debug, Clone, Default)]
+pub struct LogFileOptions {
+    /// Start a new file once the current one reaches this many bytes
+    pub max_size: Option<u64>,
+    /// Keep at most this many files, including the current one
+    pub keep_files: Option<usize>,
+    /// Remove files last written more than this many days ago
+    pub keep_days: Option<u64>,
+    /// Also log rime-tui's own events, like the commands run
+    pub events: bool,
+}
+
+/// A log file named after the time it's created, like `20230401-120000.log`.
+///
+/// Every line is flushed as it's written, so a crash doesn't lose the tail.
+pub struct LogFile {
+    dir: PathBuf,
+    options: LogFileOptions,
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl LogFile {
+    /// Creates a new file in `dir`, and removes the old ones beyond the retention limits
+    pub fn create(dir: &Path, options: LogFileOptions) -> io::Result<Self> {
+        let (path, file) = create_file(dir)?;
+        let log_file = Self {
+            dir: dir.into(),
+            options,
+            path,
+            writer: BufWriter::new(file),
+            size: 0,
+        };
+        log_file.remove_old_files()?;
+        Ok(log_file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if let Some(max_size) = self.options.max_size {
+            if self.size > 0 && self.size + len > max_size {
+                self.rotate()?;
+            }
+        }
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let (path, file) = create_file(&self.dir)?;
+        self.path = path;
+        self.writer = BufWriter::new(file);
+        self.size = 0;
+        self.remove_old_files()
+    }
+
+    /// Files that can't be inspected or removed are skipped, as another rime-tui
+    /// may be cleaning up the same directory.
+    fn remove_old_files(&self) -> io::Result<()> {
+        let name_regex = Regex::new(r"^\d{8}-\d{6}(-\d+)?\.log$").unwrap();
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if !name_regex.is_match(name) || entry.path() == self.path {
+                continue;
+            }
+            let Ok(modified) = entry.metadata().and_then(|m| m.modified()) else {
+                continue;
+            };
+            files.push((entry.path(), modified));
+        }
+        // newest first
+        files.sort_by_key(|f| Reverse(f.1));
+
+        let now = SystemTime::now();
+        for (i, (path, modified)) in files.iter().enumerate() {
+            let too_many = matches!(self.options.keep_files, Some(n) if i + 1 >= n);
+            let too_old = matches!(
+                self.options.keep_days,
+                Some(days) if now.duration_since(*modified).unwrap_or_default()
+                    > Duration::from_secs(days * 24 * 60 * 60)
+            );
+            if too_many || too_old {
+                let _ = fs::remove_file(path);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates `<time>.log` in `dir`, or `<time>-<n>.log` if it exists
+fn create_file(dir: &Path) -> io::Result<(PathBuf, File)> {
+    let time = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+    for n in 0.. {
+        let name = match n {
+            0 => format!("{}.log", time),
+            n => format!("{}-{}.log", time, n),
+        };
+        let path = dir.join(name);
+        match File::options().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+/// Makes `file` the log file written by [`write_line`] and [`log_event`]
+pub fn install(file: LogFile) {
+    *LOG_FILE.lock().unwrap() = Some(file);
+}
+
+/// Writes a line to the installed log file, if any
+pub fn write_line(line: &str) -> io::Result<()> {
+    match &mut *LOG_FILE.lock().unwrap() {
+        Some(f) => f.write_line(line),
+        None => Ok(()),
+    }
+}
+
+/// Logs an event of rime-tui itself, if enabled by [`LogFileOptions::events`].
+///
+/// The line starts with [`EVENT_PREFIX`] and the local time.
+pub fn log_event(message: fmt::Arguments) {
+    let mut guard = LOG_FILE.lock().unwrap();
+    let Some(f) = &mut *guard else {
+        return;
+    };
+    if !f.options.events {
+        return;
+    }
+    let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+    let _ = f.write_line(&format!("{} {} {}", EVENT_PREFIX, time, message));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn log_files(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Creates a log file named `name` in `dir`, last written `age` ago
+    fn old_file(dir: &Path, name: &str, age: Duration) {
+        let file = File::create(dir.join(name)).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn writes_lines() {
+        let dir = tempdir().unwrap();
+        let mut file = LogFile::create(dir.path(), Default::default()).unwrap();
+        file.write_line("a").unwrap();
+        file.write_line("b").unwrap();
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "a\nb\n");
+        let name = file.path().file_name().unwrap().to_str().unwrap();
+        assert!(Regex::new(r"^\d{8}-\d{6}\.log$").unwrap().is_match(name));
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = tempdir().unwrap();
+        let options = LogFileOptions {
+            max_size: Some(4),
+            ..Default::default()
+        };
+        let mut file = LogFile::create(dir.path(), options).unwrap();
+        file.write_line("a").unwrap();
+        file.write_line("b").unwrap();
+        let first = file.path().to_path_buf();
+        // a line longer than the limit still goes into a file of its own
+        file.write_line("long line").unwrap();
+        assert_ne!(file.path(), first);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "a\nb\n");
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "long line\n");
+        assert_eq!(log_files(dir.path()).len(), 2);
+    }
+
+    #[test]
+    fn removes_old_files() {
+        let dir = tempdir().unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+        old_file(dir.path(), "20230101-000000.log", day * 10);
+        old_file(dir.path(), "20230102-000000.log", day * 3);
+        old_file(dir.path(), "20230103-000000-1.log", day * 2);
+        old_file(dir.path(), "20230104-000000.log", day);
+        // not a log file
+        old_file(dir.path(), "notes.txt", day * 10);
+
+        let options = LogFileOptions {
+            keep_files: Some(3),
+            keep_days: Some(5),
+            ..Default::default()
+        };
+        let file = LogFile::create(dir.path(), options).unwrap();
+        let current = file.path().file_name().unwrap().to_str().unwrap();
+        let mut expected = vec![
+            "20230103-000000-1.log",
+            "20230104-000000.log",
+            "notes.txt",
+            current,
+        ];
+        expected.sort();
+        assert_eq!(log_files(dir.path()), expected);
+    }
+
+    #[test]
+    fn names_files_apart_in_the_same_second() {
+        let dir = tempdir().unwrap();
+        let paths = (0..3)
+            .map(|_| create_file(dir.path()).unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(log_files(dir.path()).len(), 3);
+        assert_ne!(paths[0], paths[1]);
+        assert_ne!(paths[1], paths[2]);
+    }
+}
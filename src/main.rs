@@ -1,210 +1,1043 @@
+use std::backtrace::Backtrace;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
 use std::fs::File;
 use std::io;
-use std::io::{BufRead, BufReader, BufWriter};
+use std::io::{stdin, BufRead, BufReader, BufWriter};
 use std::mem::MaybeUninit;
 use std::os::fd::RawFd;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
 use std::sync::{Arc, Mutex};
-use std::thread::spawn;
+use std::thread::{scope, spawn};
 use std::time::Duration;
+use std::{panic, process};
 
+use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
+use crossterm::terminal::supports_keyboard_enhancement;
 use gag::Redirect;
 use libc::{c_int, pipe};
+use librime_sys::{
+    RimeModifier_kAltMask, RimeModifier_kControlMask, RimeModifier_kLockMask,
+    RimeModifier_kReleaseMask, RimeModifier_kSuperMask,
+};
 use once_cell::sync::Lazy;
 use rime_api::engine::{DeployResult, Engine};
-use rime_api::{KeyStatus, Traits};
-use x11::keysym::*;
+use rime_api::{KeyEvent, KeyStatus, Session, Traits};
+use tui::backend::CrosstermBackend;
+use x11::keysym::{
+    XK_BackSpace, XK_Delete, XK_Down, XK_End, XK_Escape, XK_Home, XK_KP_Enter, XK_Left,
+    XK_Page_Down, XK_Page_Up, XK_Return, XK_Right, XK_Tab, XK_Up, XK_n, XK_q, XK_slash, XK_N,
+};
 
-use rime_tui::cli::build_cli;
+use rime_tui::cli::{apply_overrides, build_cli};
+use rime_tui::command::{spawn_shell, CommandAction, CommandRegistry, InputCommand};
+use rime_tui::config::{default_config_path, CandidateLayout, Config, InputBackend};
+use rime_tui::diagnostics;
+use rime_tui::evdev_input::EvdevInput;
+use rime_tui::event::{forward_signals, forward_terminal_events, spawn_input, AppEvent};
 use rime_tui::fd_reader::FdReader;
-use rime_tui::key_event::KeyEventResolver;
-use rime_tui::tui::{Candidate, TuiApp};
-use rime_tui::xinput::XInput;
+use rime_tui::glog::{LogEntry, Severity};
+use rime_tui::key_event::keysym_to_char;
+use rime_tui::key_sequence::parse_key_sequence;
+use rime_tui::log_buffer::LogBuffer;
+use rime_tui::log_file;
+use rime_tui::log_file::{log_event, LogFile};
+use rime_tui::recovery::Recovery;
+use rime_tui::rime_ext::{schema_list, SessionExt};
+use rime_tui::session::{process_key, update_ui_data};
+use rime_tui::terminal_input::{pop_keyboard_enhancement, TerminalInput};
+use rime_tui::tui::{ArgumentPrompt, Candidate, ClickTarget, SchemaPicker, Theme, TuiApp, UiData};
+use rime_tui::xinput::{parse_window_id, XInput};
 use rime_tui::{
-    load_clipboard, put_clipboard, WithLockExt, APP_NAME, DISTRIBUTION_CODE_NAME,
-    DISTRIBUTION_NAME, DISTRIBUTION_VERSION,
+    append_file, load_clipboard, open_file, put_clipboard, save_file, WithLockExt, APP_NAME,
+    DISTRIBUTION_CODE_NAME, DISTRIBUTION_NAME, DISTRIBUTION_VERSION, LAST_SCHEMA_FILE_NAME,
+    RECOVERY_DIR_NAME,
 };
 
+/// Lines scrolled by the log scrolling commands and PageUp/PageDown in the log viewer
+const LOG_SCROLL_LINES: usize = 10;
+const MOUSE_SCROLL_LINES: usize = 3;
+
 static STDERR_REDIRECT: Lazy<Mutex<Option<Redirect<RawFd>>>> = Lazy::new(|| Mutex::new(None));
 
 fn main() -> anyhow::Result<()> {
+    install_panic_hook();
+    let result = run();
+    if result.is_err() {
+        // so the error is printed on a working terminal
+        restore_terminal();
+    }
+    result
+}
+
+/// Restores the terminal and prints the panic message and a backtrace to it,
+/// then exits, as the app is left unusable by a panic on any of its threads.
+///
+/// The recovery file is kept, so the output can be recovered on the next start.
+fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        restore_terminal();
+        eprintln!("{}", info);
+        eprintln!("{}", Backtrace::force_capture());
+        process::exit(101);
+    }));
+}
+
+/// Leaves the alternate screen and raw mode, and stops redirecting stderr
+fn restore_terminal() {
+    pop_keyboard_enhancement();
+    let _ = rime_tui::tui::restore_terminal();
+    // the lock is only held briefly, but don't wait on it in a panic hook
+    if let Ok(mut r) = STDERR_REDIRECT.try_lock() {
+        drop(r.take());
+    }
+}
+
+fn run() -> anyhow::Result<()> {
     let matches = build_cli().get_matches();
-    let schema = matches.get_one::<String>("schema");
-    let user_dir = matches.get_one::<String>("user-dir").unwrap();
-    let shared_dir = matches.get_one::<String>("shared-dir").unwrap();
-    let exit_command = matches.get_one::<String>("exit-command").unwrap();
-    let copy_command = matches.get_one::<String>("copy-command").unwrap();
-    let load_command = matches.get_one::<String>("load-command").unwrap();
-    let log_dir = matches.get_one::<String>("log-dir");
-
-    let log_file = if let Some(d) = log_dir {
-        let log_filename = chrono::Local::now().format("%Y%m%d-%H%M%S.log").to_string();
-        let file = File::options()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(PathBuf::from(d).join(log_filename))?;
-        Some(BufWriter::new(file))
-    } else {
-        None
+    let config_path = matches
+        .get_one::<String>("config")
+        .map(PathBuf::from)
+        .or_else(default_config_path);
+    let print_on_exit = matches.get_flag("print-on-exit");
+    let output_file = matches.get_one::<String>("output-file");
+    let edit_file = matches.get_one::<String>("edit").map(PathBuf::from);
+    let window_id = match matches.get_one::<String>("window-id") {
+        None => None,
+        Some(id) => {
+            Some(parse_window_id(id).ok_or_else(|| anyhow::anyhow!("Invalid window ID: {}", id))?)
+        }
+    };
+
+    let mut config = match &config_path {
+        None => Config::default(),
+        Some(p) => Config::load(p)?,
+    };
+    apply_overrides(&mut config, &matches);
+    if matches.get_flag("print-config") {
+        print!("{}", config.to_toml());
+        return Ok(());
+    }
+    let schema = config.schema.as_ref();
+    let user_dir = &config.user_dir;
+    let log_dir = config.log_dir.as_ref();
+    let input_backend = config.input_backend;
+    let evdev_device = config.evdev_device.as_ref().map(PathBuf::from);
+
+    let mut commands = CommandRegistry::with_builtins(&config.command_prefix);
+    for c in &config.commands {
+        commands.register(c.into());
+    }
+    for (input, name) in &config.aliases {
+        if commands.get(name).is_none() {
+            return Err(anyhow::anyhow!(
+                "Unknown command in alias {}: {}",
+                input,
+                name
+            ));
+        }
+        commands.alias(input, name);
+    }
+    let key_bindings = parse_key_bindings(&config, &commands)?;
+    let theme = Theme::from_config(&config.theme)?;
+
+    if let Some(script) = matches.get_one::<String>("script") {
+        let Some(engine) = create_engine(&config, schema)? else {
+            return Err(anyhow::anyhow!("Deployment failed"));
+        };
+        return run_script(&engine, script);
+    }
+
+    if let Some(d) = log_dir {
+        log_file::install(LogFile::create(Path::new(d), config.log_file_options())?);
+    }
+
+    // open the device before taking over the screen, so a permission error stays readable
+    let evdev_input = match input_backend {
+        InputBackend::Evdev => Some(EvdevInput::open(evdev_device.as_deref())?),
+        InputBackend::X11 | InputBackend::Terminal => None,
     };
 
-    let app = TuiApp::new()?;
+    let mut app = TuiApp::new(theme, config.layout.clone())?;
+    app.ui_data.log = LogBuffer::new(config.log_lines);
+    app.ui_data.log.set_min_severity(config.log_level);
     let app = Arc::new(Mutex::new(app));
 
-    app.with_lock(|x| x.start().unwrap()).unwrap();
+    if input_backend == InputBackend::Terminal && !supports_keyboard_enhancement()? {
+        return Err(anyhow::anyhow!(
+            "The terminal doesn't support the kitty keyboard protocol"
+        ));
+    }
 
-    app.with_lock(|mut x| {
-        x.start()?;
-        x.redraw()
-    })
-    .unwrap()?;
+    let terminal_events = app
+        .with_lock(|mut x| {
+            let events = x.start()?;
+            x.redraw()?;
+            io::Result::Ok(events)
+        })
+        .unwrap()?;
 
+    let (event_sender, events) = channel();
+    diagnostics::set_sender(Some(event_sender.clone()));
+    // right away, so a signal during the deployment doesn't kill us with the terminal in raw mode
+    forward_signals(event_sender.clone())?;
+    let terminal_backend = input_backend == InputBackend::Terminal;
+    // XInput watches the focus of the terminal window itself
+    let focus_events = input_backend != InputBackend::X11;
+    // also right away, for Ctrl+C, which raw mode turns into a key
+    forward_terminal_events(
+        terminal_events,
+        event_sender.clone(),
+        terminal_backend,
+        focus_events,
+    );
+
+    // librime logs to stderr (also when it writes log files), which would break the screen
     let app_clone = Arc::clone(&app);
     spawn(move || {
         let stderr_reader = setup_stderr_redirect().unwrap();
         let app = app_clone;
-        let mut log_file = log_file;
 
-        let reader = BufReader::new(stderr_reader);
-        for line in reader.lines().map(Result::unwrap) {
-            if let Some(w) = &mut log_file {
-                use std::io::Write;
-                writeln!(w, "{}", line).unwrap();
+        let mut reader = BufReader::new(stderr_reader);
+        let mut buf = Vec::new();
+        let mut severity = Severity::Info;
+        let mut write_failed = false;
+        let mut redraw_failed = false;
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    diagnostics::error(format_args!("Failed to read librime's log: {}", e));
+                    break;
+                }
+            }
+            // librime may log bytes that aren't UTF-8, like paths or broken dictionary entries
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches(['\n', '\r']);
+            // keep draining stderr, and showing the lines, without the log file
+            if let Err(e) = log_file::write_line(line) {
+                if !write_failed {
+                    diagnostics::error(format_args!("Failed to write the log file: {}", e));
+                }
+                write_failed = true;
             }
 
-            app.with_lock(|mut x| {
-                x.ui_data.log.push(line);
-                x.redraw()
-            })
-            .unwrap()
-            .expect("Redraw error");
+            let entry = LogEntry::parse(line, severity);
+            severity = entry.severity;
+            let redrawn = app
+                .with_lock(|mut x| {
+                    x.ui_data.push_log(entry);
+                    x.redraw()
+                })
+                .unwrap();
+            if let Err(e) = redrawn {
+                if !redraw_failed {
+                    diagnostics::error(format_args!("Failed to redraw: {}", e));
+                }
+                redraw_failed = true;
+            }
         }
     });
 
-    let mut traits = Traits::new();
-    traits.set_user_data_dir(user_dir);
-    traits.set_shared_data_dir(shared_dir);
-    traits.set_distribution_name(DISTRIBUTION_NAME);
-    traits.set_distribution_code_name(DISTRIBUTION_CODE_NAME);
-    traits.set_distribution_version(DISTRIBUTION_VERSION);
-    traits.set_app_name(APP_NAME);
+    // an explicitly given schema wins over the one last chosen in the schema picker
+    let last_schema_path = PathBuf::from(user_dir).join(LAST_SCHEMA_FILE_NAME);
+    let last_schema = fs::read_to_string(&last_schema_path)
+        .ok()
+        .map(|s| String::from(s.trim()))
+        .filter(|s| !s.is_empty());
+    // deploying can take minutes, so a signal meanwhile is handled here; other events wait
+    let mut pending = Vec::new();
+    let engine = scope(|s| {
+        let deploying = s.spawn(|| create_engine(&config, schema.or(last_schema.as_ref())));
+        while !deploying.is_finished() {
+            match events.recv_timeout(Duration::from_secs_f64(0.1)) {
+                Ok(AppEvent::Terminate(signal)) => {
+                    log_event(format_args!(
+                        "terminated by signal {} while deploying",
+                        signal
+                    ));
+                    restore_terminal();
+                    process::exit(128 + signal);
+                }
+                Ok(event) => pending.push(event),
+                Err(_) => {}
+            }
+        }
+        deploying.join().unwrap()
+    })?;
+    let Some(engine) = engine else {
+        return Err(anyhow::anyhow!("Deployment failed"));
+    };
+    let mut pending = pending.into_iter();
+    app.with_lock(|mut x| {
+        x.ui_data.status = engine.session().unwrap().status_ext();
+        x.redraw()
+    })
+    .unwrap()?;
 
-    let mut engine = Engine::new(traits);
-    let deploy_result = engine.wait_for_deploy_result(Duration::from_secs_f64(0.1));
-    match deploy_result {
-        DeployResult::Success => {
-            eprintln!("Deployment succeeded");
+    let recovery_dir = PathBuf::from(user_dir).join(RECOVERY_DIR_NAME);
+    fs::create_dir_all(&recovery_dir)?;
+    let mut recovery;
+    if let Some(path) = &edit_file {
+        recovery = Recovery::for_edit(&recovery_dir, path)?;
+        let mut recovered = recovery.load()?;
+        if recovered.is_some() && !recovery.is_newer_than(path)? {
+            // the file has been written since, e.g. with another editor
+            fs::remove_file(recovery.path())?;
+            diagnostics::info(format_args!(
+                "Discarded {}, as {} has been modified since",
+                recovery.path().display(),
+                path.display()
+            ));
+            recovered = None;
         }
-        DeployResult::Failure => {
-            eprintln!("Deployment failed");
-            return Ok(());
+        let text = match recovered {
+            Some(text) => {
+                diagnostics::info(format_args!(
+                    "Recovered an unfinished edit of {} from {}",
+                    path.display(),
+                    recovery.path().display()
+                ));
+                text
+            }
+            None => match open_file(path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                r => r?,
+            },
+        };
+        app.with_lock(|mut x| {
+            x.ui_data.output.reset(text);
+            x.ui_data.output.move_to_start();
+            x.redraw()
+        })
+        .unwrap()?;
+    } else {
+        recovery = Recovery::for_session(&recovery_dir);
+        if let Some((orphan, text)) = Recovery::find_orphaned(&recovery_dir)? {
+            let saved = app
+                .with_lock(|mut x| {
+                    x.ui_data.output.reset(text);
+                    let saved = recovery.save_if_changed(&x.ui_data.output);
+                    x.redraw().map(|_| saved)
+                })
+                .unwrap()?;
+            match saved {
+                // it's taken over by this session's recovery file
+                Ok(_) => fs::remove_file(&orphan)?,
+                // keep it, so it's still there for the next start
+                Err(e) => {
+                    diagnostics::error(format_args!("Failed to write the recovery file: {}", e))
+                }
+            }
+            diagnostics::info(format_args!(
+                "Recovered the output of an unfinished session from {}",
+                orphan.display()
+            ));
         }
     }
-    engine.create_session()?;
-    let session = engine.session().unwrap();
-    if let Some(schema) = schema {
-        session.select_schema(schema);
-    }
+    // the file path of the last save/open/append command
+    let mut file_path: Option<PathBuf> = None;
+    let exit_status;
+    // set if the program is shutting down on a signal
+    let mut terminated = false;
 
     let engine = RefCell::new(engine);
-    let mut key_resolver = KeyEventResolver::new(|ke| {
+    let on_key = |ke: KeyEvent| {
         let engine = engine.borrow();
         let session = engine.session().unwrap();
         let mut app = app.lock().unwrap();
-        let ui_data = &mut app.ui_data;
-
-        let key_status = session.process_key(ke);
-        if key_status == KeyStatus::Pass && ke.modifiers == 0 {
-            // default behaviors
-            #[allow(non_upper_case_globals)]
-            match ke.key_code as u32 {
-                k @ XK_a..=XK_z => ui_data.output.push(char::from((k - XK_a) as u8 + b'a')),
-                k @ XK_0..=XK_9 => ui_data.output.push(char::from((k - XK_0) as u8 + b'0')),
-                XK_BackSpace => {
-                    ui_data.output.pop();
-                }
-                XK_Return => ui_data.output.push('\n'),
-                XK_space => ui_data.output.push(' '),
-                _ => {}
-            }
-        }
-
-        let context = session.context();
-        let menu = &context.as_ref().unwrap().menu;
-        let preedit = context.as_ref().unwrap().composition.preedit.unwrap_or("");
-        let select_labels = &context.as_ref().unwrap().select_labels;
-
-        ui_data.preedit = String::from(preedit);
-        ui_data.candidates = menu
-            .candidates
-            .iter()
-            .enumerate()
-            .map(|(i, x)| Candidate {
-                text: x.text.into(),
-                comment: x.comment.unwrap_or("").into(),
-                highlighted: i == menu.highlighted_candidate_index as usize,
-            })
-            .collect();
-        if let Some(l) = select_labels {
-            ui_data.select_labels = Some(l.iter().map(|x| String::from(*x)).collect::<Vec<_>>());
-        }
-        drop(context);
-        let commit = session.commit();
-        // TODO: if taking the ownership of `commit` in the `match` below,
-        //  `c.text` will be freed and thus its data is invalid
-        let commit = match &commit {
-            None => "",
-            Some(c) => c.text,
-        };
-        ui_data.output.push_str(commit);
-
+        let status = process_key(session, &mut app.ui_data, ke);
         app.redraw().unwrap();
-    });
+        status
+    };
 
-    let input = XInput::new(None);
+    // for the results of shell commands
+    let command_events = event_sender.clone();
+    let terminal_input = match input_backend {
+        InputBackend::Terminal => Some(TerminalInput::new()?),
+        InputBackend::Evdev => {
+            let input = evdev_input.expect("opened for the evdev backend");
+            spawn_input(move || Ok(input), event_sender);
+            None
+        }
+        InputBackend::X11 => {
+            spawn_input(move || XInput::new(None, window_id), event_sender);
+            None
+        }
+    };
+    if terminal_backend {
+        app.with_lock(|mut x| {
+            x.ui_data.input_live = true;
+            x.redraw()
+        })
+        .unwrap()?;
+    }
     loop {
-        let Some((_, event)) = input.next_event() else {
-            continue
+        let ke = match pending.next().map_or_else(|| events.recv(), Ok)? {
+            // evdev reads the keyboard system-wide, also while typing into other windows
+            AppEvent::Key(_) if !app.lock().unwrap().ui_data.input_live => continue,
+            AppEvent::Key(ke) => ke,
+            AppEvent::Mouse(e) => {
+                let engine = engine.borrow();
+                let session = engine.session().unwrap();
+                let mut app = app.lock().unwrap();
+                if app.ui_data.schema_picker.is_none() {
+                    handle_mouse(session, &commands, &config.option_keys, &mut app, e)?;
+                }
+                app.redraw()?;
+                continue;
+            }
+            AppEvent::Resize => {
+                app.lock().unwrap().redraw()?;
+                continue;
+            }
+            AppEvent::InputLive(live) => {
+                app.with_lock(|mut x| {
+                    x.ui_data.input_live = live;
+                    x.redraw()
+                })
+                .unwrap()?;
+                continue;
+            }
+            AppEvent::Terminate(signal) => {
+                log_event(format_args!("terminated by signal {}", signal));
+                // keep the output in the recovery file for the next start, rather than writing it out
+                if let Err(e) = recovery.save_if_changed(&app.lock().unwrap().ui_data.output) {
+                    diagnostics::error(format_args!("Failed to write the recovery file: {}", e));
+                }
+                terminated = true;
+                exit_status = 128 + signal;
+                break;
+            }
+            AppEvent::Message(entry) => {
+                log_event(format_args!("{}", entry.message));
+                app.with_lock(|mut x| {
+                    x.ui_data.push_log(entry);
+                    x.redraw()
+                })
+                .unwrap()?;
+                continue;
+            }
+            AppEvent::ShellDone {
+                name,
+                replace,
+                result,
+            } => {
+                let mut app = app.lock().unwrap();
+                let output = &mut app.ui_data.output;
+                match result {
+                    Ok(stdout) if replace => output.set_text(stdout),
+                    Ok(stdout) => output.insert_str(&stdout),
+                    Err(e) => diagnostics::error(format_args!("{} failed: {}", name, e)),
+                }
+                app.redraw()?;
+                if let Err(e) = recovery.save_if_changed(&app.ui_data.output) {
+                    diagnostics::error(format_args!("Failed to write the recovery file: {}", e));
+                }
+                continue;
+            }
+            AppEvent::InputError(e) => {
+                log_event(format_args!("input error: {}", e));
+                return Err(e.into());
+            }
         };
 
-        key_resolver.on_key_event(&event);
+        if app.lock().unwrap().ui_data.log_viewer.is_some() {
+            let mut app = app.lock().unwrap();
+            handle_log_viewer_key(&mut app.ui_data, ke);
+            app.redraw()?;
+            continue;
+        }
+
+        // set if the invocation comes from the argument prompt, so it doesn't open it again
+        let mut prompted = false;
+        let mut invocation = None;
+        if app.lock().unwrap().ui_data.argument_prompt.is_some() {
+            let mut app = app.lock().unwrap();
+            match handle_argument_prompt_key(&mut app.ui_data, ke) {
+                Some((name, argument)) => {
+                    invocation = commands.get(&name).map(|c| (c, argument));
+                    prompted = true;
+                }
+                None => {
+                    app.redraw()?;
+                    continue;
+                }
+            }
+        }
+
+        if app.lock().unwrap().ui_data.schema_picker.is_some() {
+            let engine = engine.borrow();
+            let session = engine.session().unwrap();
+            let mut app = app.lock().unwrap();
+            if let Some(id) = handle_schema_picker_key(session, &mut app.ui_data, ke) {
+                if let Err(e) = save_file(&last_schema_path, &id) {
+                    diagnostics::error(format_args!("Failed to remember the schema: {}", e));
+                }
+            }
+            app.redraw()?;
+            continue;
+        }
+
+        let preedit = app.lock().unwrap().ui_data.preedit.clone();
+        // CapsLock doesn't change which command a key runs
+        let modifiers = ke.modifiers & !(RimeModifier_kLockMask as i32);
+        #[allow(non_upper_case_globals)]
+        match ke.key_code as u32 {
+            _ if prompted => {}
+            XK_Tab if modifiers == 0 && commands.is_command_input(&preedit) => {
+                if let Some(suffix) = commands.completion_suffix(&preedit) {
+                    for ke in parse_key_sequence(&suffix)? {
+                        on_key(ke);
+                    }
+                }
+            }
+            XK_Return if modifiers == 0 && commands.parse(&preedit).is_some() => {
+                invocation = commands
+                    .parse(&preedit)
+                    .map(|(c, argument)| (c, argument.map(String::from)));
+            }
+            _ => {
+                if on_key(ke) == KeyStatus::Pass {
+                    let binding = key_bindings
+                        .iter()
+                        .find(|(k, _)| k.key_code == ke.key_code && k.modifiers == modifiers);
+                    if let Some((_, input)) = binding {
+                        invocation = commands
+                            .parse(input)
+                            .map(|(c, argument)| (c, argument.map(String::from)));
+                    }
+                }
+            }
+        }
 
-        // custom commands
         let mut app_guard = app.lock().unwrap();
-        let preedit = &app_guard.ui_data.preedit;
+        let ui_data = &mut app_guard.ui_data;
 
         let engine = engine.borrow();
         let session = engine.session().unwrap();
-        match preedit.as_str() {
-            _ if preedit == exit_command => {
-                break;
-            }
-            _ if preedit == copy_command => {
-                put_clipboard(app_guard.ui_data.output.as_str())?;
-                app_guard.ui_data.preedit.clear();
+        match invocation {
+            Some((command, None)) if command.takes_argument && !prompted => {
+                let text = match command.action {
+                    CommandAction::Save | CommandAction::Open | CommandAction::Append => {
+                        file_path.as_ref().map(|p| p.display().to_string())
+                    }
+                    _ => None,
+                };
+                let label = format!("{}{}", commands.prefix(), command.name);
+                ui_data.argument_prompt = Some(ArgumentPrompt::new(
+                    &command.name,
+                    label,
+                    &text.unwrap_or_default(),
+                ));
+                ui_data.clear_composition();
                 session.simulate_key_sequence("{Escape}")?;
-                app_guard.redraw()?;
             }
-            _ if preedit == load_command => {
-                app_guard.ui_data.output = load_clipboard()?;
-                app_guard.ui_data.preedit.clear();
+            Some((command, argument)) => {
+                match &argument {
+                    None => log_event(format_args!("command: {}", command.name)),
+                    Some(a) => log_event(format_args!("command: {} {}", command.name, a)),
+                }
+                let exit = execute_command(
+                    command,
+                    session,
+                    argument.as_deref(),
+                    ui_data,
+                    edit_file.as_deref(),
+                    &mut file_path,
+                    &command_events,
+                    &config.option_keys,
+                )?;
+                if let Some(status) = exit {
+                    exit_status = status;
+                    break;
+                }
+                ui_data.clear_composition();
                 session.simulate_key_sequence("{Escape}")?;
-                app_guard.redraw()?;
             }
-            _ => {}
+            None if commands.is_command_input(&ui_data.preedit) => {
+                show_completions(ui_data, &commands);
+            }
+            None => {}
+        }
+        app_guard.redraw()?;
+        if let Err(e) = recovery.save_if_changed(&app_guard.ui_data.output) {
+            diagnostics::error(format_args!("Failed to write the recovery file: {}", e));
         }
         drop(app_guard);
     }
 
+    drop(terminal_input);
     drop(engine);
-    app.with_lock(|mut x| x.stop()).unwrap()?;
+    diagnostics::set_sender(None);
+    let output = app
+        .with_lock(|mut x| {
+            x.stop()?;
+            io::Result::Ok(String::from(x.ui_data.output.as_str()))
+        })
+        .unwrap()?;
+
+    restore_terminal();
+
+    if terminated {
+        process::exit(exit_status);
+    }
+
+    if let Some(f) = output_file {
+        save_file(Path::new(f), &output)?;
+    }
+    if print_on_exit {
+        print!("{}", output);
+    }
+    recovery.remove()?;
 
-    if let Some(r) = STDERR_REDIRECT.lock().unwrap().take() {
-        drop(r);
+    if exit_status != 0 {
+        process::exit(exit_status);
+    }
+    Ok(())
+}
+
+/// Parses the keys of `config.key_bindings`, and checks their commands exist.
+///
+/// Returns the keys and the command inputs (with the prefix).
+fn parse_key_bindings(
+    config: &Config,
+    commands: &CommandRegistry,
+) -> anyhow::Result<Vec<(KeyEvent, String)>> {
+    let mut bindings = Vec::new();
+    for (keys, command) in &config.key_bindings {
+        let key = match parse_key_sequence(keys)?[..] {
+            [key] => key,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Key binding must be a single key: {}",
+                    keys
+                ))
+            }
+        };
+        let input = format!("{}{}", commands.prefix(), command);
+        if commands.parse(&input).is_none() {
+            return Err(anyhow::anyhow!(
+                "Unknown command in key binding {}: {}",
+                keys,
+                command
+            ));
+        }
+        bindings.push((key, input));
     }
+    Ok(bindings)
+}
 
+/// Runs an input command. Returns the exit status if the program should exit.
+#[allow(clippy::too_many_arguments)]
+fn execute_command(
+    command: &InputCommand,
+    session: &Session,
+    argument: Option<&str>,
+    ui_data: &mut UiData,
+    edit_file: Option<&Path>,
+    file_path: &mut Option<PathBuf>,
+    events: &Sender<AppEvent>,
+    option_keys: &BTreeMap<String, String>,
+) -> anyhow::Result<Option<i32>> {
+    let output = &mut ui_data.output;
+    match &command.action {
+        // in edit mode, quitting without "done" is a failure, like an aborting editor
+        CommandAction::Exit => return Ok(Some(if edit_file.is_some() { 1 } else { 0 })),
+        CommandAction::Done => match edit_file {
+            None => return Ok(Some(0)),
+            Some(path) => match save_file(path, output.as_str()) {
+                Ok(_) => return Ok(Some(0)),
+                Err(e) => {
+                    diagnostics::error(format_args!("Failed to write {}: {}", path.display(), e))
+                }
+            },
+        },
+        CommandAction::Copy => {
+            if let Err(e) = put_clipboard(output.as_str()) {
+                diagnostics::error(format_args!("Failed to copy to the clipboard: {}", e));
+            }
+        }
+        CommandAction::Load => match load_clipboard() {
+            Ok(text) => output.set_text(text),
+            Err(e) => diagnostics::error(format_args!("Failed to load the clipboard: {}", e)),
+        },
+        CommandAction::Undo => {
+            output.undo();
+        }
+        CommandAction::Redo => {
+            output.redo();
+        }
+        CommandAction::ToggleOption => match argument {
+            None => diagnostics::error(format_args!("No option given: {} <option>", command.name)),
+            Some(option) => toggle_option(session, ui_data, option_keys, option),
+        },
+        CommandAction::ToggleCandidateLayout => {
+            ui_data.candidate_layout = match ui_data.candidate_layout {
+                CandidateLayout::Vertical => CandidateLayout::Horizontal,
+                CandidateLayout::Horizontal => CandidateLayout::Vertical,
+            };
+        }
+        CommandAction::ToggleLog => ui_data.log_hidden = !ui_data.log_hidden,
+        CommandAction::ToggleMaximize => ui_data.output_maximized = !ui_data.output_maximized,
+        CommandAction::ScrollLogUp => {
+            let max = ui_data.log.len().saturating_sub(1);
+            ui_data.log_scroll = (ui_data.log_scroll + LOG_SCROLL_LINES).min(max);
+        }
+        CommandAction::ScrollLogDown => {
+            ui_data.log_scroll = ui_data.log_scroll.saturating_sub(LOG_SCROLL_LINES);
+        }
+        CommandAction::LogViewer => ui_data.open_log_viewer(),
+        CommandAction::LogSeverity => {
+            let severity = match argument {
+                None => Some(ui_data.log.min_severity().cycle()),
+                Some(a) => Severity::from_name(a),
+            };
+            match severity {
+                Some(s) => {
+                    ui_data.set_log_severity(s);
+                    diagnostics::info(format_args!("Showing log lines of {} and above", s.name()));
+                }
+                None => diagnostics::error(format_args!(
+                    "Unknown severity: {}",
+                    argument.unwrap_or_default()
+                )),
+            }
+        }
+        CommandAction::Schema => {
+            let schemas = schema_list();
+            if schemas.is_empty() {
+                diagnostics::error(format_args!("No schemas found"));
+            } else {
+                let current = ui_data.status.as_ref().map(|s| &s.schema);
+                ui_data.schema_picker = Some(SchemaPicker::new(schemas, current));
+            }
+        }
+        action @ (CommandAction::Save | CommandAction::Open | CommandAction::Append) => {
+            if let Some(a) = argument {
+                *file_path = Some(PathBuf::from(a));
+            }
+            let Some(path) = file_path else {
+                diagnostics::error(format_args!("No file path given: {} <path>", command.name));
+                return Ok(None);
+            };
+            let result = match action {
+                CommandAction::Save => save_file(path, output.as_str()),
+                CommandAction::Append => append_file(path, output.as_str()),
+                _ => open_file(path).map(|text| output.set_text(text)),
+            };
+            match result {
+                Ok(_) => diagnostics::info(format_args!("{}: {}", command.name, path.display())),
+                Err(e) => diagnostics::error(format_args!(
+                    "{} {} failed: {}",
+                    command.name,
+                    path.display(),
+                    e
+                )),
+            }
+        }
+        // the output is changed on AppEvent::ShellDone
+        CommandAction::Shell { .. } => {
+            spawn_shell(command, argument, output.as_str(), events.clone())
+        }
+    }
+    Ok(None)
+}
+
+/// Toggles a Rime option with its key sequence in `option_keys`, and reports the new value
+fn toggle_option(
+    session: &Session,
+    ui_data: &mut UiData,
+    option_keys: &BTreeMap<String, String>,
+    option: &str,
+) {
+    let Some(keys) = option_keys.get(option) else {
+        diagnostics::error(format_args!(
+            "No keys to toggle {} with; add them to option_keys in the config",
+            option
+        ));
+        return;
+    };
+    let before = ui_data.status.as_ref().and_then(|s| s.option(option));
+    match session.toggle_option(option, keys) {
+        Ok(Some(value)) if Some(value) != before => diagnostics::info(format_args!(
+            "{}: {}",
+            option,
+            if value { "on" } else { "off" }
+        )),
+        Ok(Some(_)) => diagnostics::error(format_args!(
+            "{} didn't toggle {}; check option_keys in the config",
+            keys, option
+        )),
+        Ok(None) => diagnostics::info(format_args!("{}: toggled", option)),
+        Err(e) => diagnostics::error(format_args!("Failed to toggle {}: {}", option, e)),
+    }
+    update_ui_data(session, ui_data);
+}
+
+/// Toggles options in the status bar, selects candidates (or completes command names)
+/// or turns their pages, and scrolls the log
+fn handle_mouse(
+    session: &Session,
+    commands: &CommandRegistry,
+    option_keys: &BTreeMap<String, String>,
+    app: &mut TuiApp<CrosstermBackend<BufWriter<File>>>,
+    e: MouseEvent,
+) -> anyhow::Result<()> {
+    let target = app.click_target(e.column, e.row);
+    let ui_data = &mut app.ui_data;
+    // the Candidates pane lists the completions of a command name instead
+    let completions = commands.complete(&ui_data.preedit);
+    match (e.kind, target) {
+        (MouseEventKind::Down(MouseButton::Left), Some(ClickTarget::ToggleOption(option))) => {
+            toggle_option(session, ui_data, option_keys, option);
+        }
+        (MouseEventKind::Down(MouseButton::Left), Some(ClickTarget::Candidate(i)))
+            if !completions.is_empty() =>
+        {
+            let typed = &ui_data.preedit[commands.prefix().len()..];
+            if let Some(suffix) = completions.get(i).map(|c| &c.name[typed.len()..]) {
+                for ke in parse_key_sequence(suffix)? {
+                    process_key(session, ui_data, ke);
+                }
+                show_completions(ui_data, commands);
+            }
+        }
+        (MouseEventKind::Down(MouseButton::Left), Some(ClickTarget::Candidate(i)))
+            if session.select_candidate_on_current_page(i) =>
+        {
+            update_ui_data(session, ui_data);
+        }
+        (MouseEventKind::ScrollUp, Some(ClickTarget::Candidate(_) | ClickTarget::Candidates))
+            if completions.is_empty() =>
+        {
+            session.simulate_key_sequence("{Page_Up}")?;
+            update_ui_data(session, ui_data);
+        }
+        (MouseEventKind::ScrollDown, Some(ClickTarget::Candidate(_) | ClickTarget::Candidates))
+            if completions.is_empty() =>
+        {
+            session.simulate_key_sequence("{Page_Down}")?;
+            update_ui_data(session, ui_data);
+        }
+        (MouseEventKind::Down(MouseButton::Left), Some(ClickTarget::LogBadge)) => {
+            ui_data.open_log_viewer();
+        }
+        (MouseEventKind::ScrollUp, Some(ClickTarget::Log)) => match &mut ui_data.log_viewer {
+            Some(v) => v.scroll_up(MOUSE_SCROLL_LINES, &ui_data.log),
+            None => {
+                let max = ui_data.log.len().saturating_sub(1);
+                ui_data.log_scroll = (ui_data.log_scroll + MOUSE_SCROLL_LINES).min(max);
+            }
+        },
+        (MouseEventKind::ScrollDown, Some(ClickTarget::Log)) => match &mut ui_data.log_viewer {
+            Some(v) => v.scroll_down(MOUSE_SCROLL_LINES),
+            None => ui_data.log_scroll = ui_data.log_scroll.saturating_sub(MOUSE_SCROLL_LINES),
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handles a key while the log viewer is open
+#[allow(non_upper_case_globals)]
+fn handle_log_viewer_key(ui_data: &mut UiData, ke: KeyEvent) {
+    if ke.modifiers & RimeModifier_kReleaseMask as i32 != 0 {
+        return;
+    }
+    let log = &ui_data.log;
+    let Some(viewer) = &mut ui_data.log_viewer else {
+        return;
+    };
+    let key = ke.key_code as u32;
+    if viewer.searching {
+        match key {
+            XK_Return | XK_KP_Enter => viewer.searching = false,
+            XK_Escape => {
+                viewer.searching = false;
+                viewer.query.clear();
+                viewer.current_match = None;
+            }
+            XK_BackSpace => {
+                viewer.query.pop();
+                viewer.search(log);
+            }
+            k if ke.modifiers & RimeModifier_kControlMask as i32 == 0 => {
+                if let Some(c) = keysym_to_char(k) {
+                    viewer.query.push(c);
+                    viewer.search(log);
+                }
+            }
+            _ => {}
+        }
+        return;
+    }
+    match key {
+        XK_Up => viewer.scroll_up(1, log),
+        XK_Down => viewer.scroll_down(1),
+        XK_Page_Up => viewer.scroll_up(LOG_SCROLL_LINES, log),
+        XK_Page_Down => viewer.scroll_down(LOG_SCROLL_LINES),
+        XK_Home => viewer.scroll_up(log.len(), log),
+        XK_End => viewer.scroll = 0,
+        XK_slash => {
+            viewer.searching = true;
+            viewer.query.clear();
+            viewer.current_match = None;
+        }
+        XK_n => viewer.search_older(log),
+        XK_N => viewer.search_newer(log),
+        XK_Escape | XK_q => ui_data.log_viewer = None,
+        _ => {}
+    }
+}
+
+/// Handles a key while the schema picker is open, and switches the schema on Return.
+///
+/// Returns the ID of the newly selected schema.
+#[allow(non_upper_case_globals)]
+fn handle_schema_picker_key(
+    session: &Session,
+    ui_data: &mut UiData,
+    ke: KeyEvent,
+) -> Option<String> {
+    if ke.modifiers & RimeModifier_kReleaseMask as i32 != 0 {
+        return None;
+    }
+    let picker = ui_data.schema_picker.as_mut()?;
+    match ke.key_code as u32 {
+        XK_Up => picker.select_previous(),
+        XK_Down => picker.select_next(),
+        XK_Escape => ui_data.schema_picker = None,
+        XK_Return | XK_KP_Enter => {
+            let schema = picker.selected().cloned();
+            ui_data.schema_picker = None;
+            let schema = schema?;
+            session.select_schema(&schema.id);
+            ui_data.status = session.status_ext();
+            ui_data.clear_composition();
+            diagnostics::info(format_args!(
+                "Switched to schema {} ({})",
+                schema.name, schema.id
+            ));
+            log_event(format_args!("schema: {}", schema.id));
+            return Some(schema.id);
+        }
+        _ => {}
+    }
+    None
+}
+
+/// Edits the argument prompt. Returns the command name and the argument
+/// (`None` if nothing is typed) when Return is pressed.
+fn handle_argument_prompt_key(
+    ui_data: &mut UiData,
+    ke: KeyEvent,
+) -> Option<(String, Option<String>)> {
+    if ke.modifiers & RimeModifier_kReleaseMask as i32 != 0 {
+        return None;
+    }
+    let input = &mut ui_data.argument_prompt.as_mut()?.input;
+    #[allow(non_upper_case_globals)]
+    match ke.key_code as u32 {
+        XK_Escape => ui_data.argument_prompt = None,
+        XK_Return | XK_KP_Enter => {
+            let prompt = ui_data.argument_prompt.take()?;
+            let argument = prompt.input.as_str().trim();
+            let argument = (!argument.is_empty()).then(|| argument.into());
+            return Some((prompt.command, argument));
+        }
+        XK_BackSpace => input.backspace(),
+        XK_Delete => input.delete(),
+        XK_Left => input.move_left(),
+        XK_Right => input.move_right(),
+        XK_Home => input.move_home(),
+        XK_End => input.move_end(),
+        k => {
+            let shortcut =
+                RimeModifier_kControlMask | RimeModifier_kAltMask | RimeModifier_kSuperMask;
+            if ke.modifiers & shortcut as i32 == 0 {
+                if let Some(c) = keysym_to_char(k) {
+                    input.insert(c);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Lists the commands matching the preedit in the Candidates pane
+fn show_completions(ui_data: &mut UiData, commands: &CommandRegistry) {
+    let completions = commands.complete(&ui_data.preedit);
+    if completions.is_empty() {
+        return;
+    }
+    ui_data.candidates = completions
+        .iter()
+        .enumerate()
+        .map(|(i, c)| Candidate {
+            text: format!("{}{}", commands.prefix(), c.name),
+            comment: format!(" {}", c.description),
+            highlighted: i == 0,
+        })
+        .collect();
+    ui_data.select_labels = None;
+    ui_data.page_no = 0;
+    ui_data.is_last_page = true;
+}
+
+/// Returns `None` if the deployment fails
+fn create_engine(config: &Config, schema: Option<&String>) -> anyhow::Result<Option<Engine>> {
+    let mut traits = Traits::new();
+    traits.set_user_data_dir(&config.user_dir);
+    traits.set_shared_data_dir(&config.shared_dir);
+    if let Some(d) = &config.rime_log_dir {
+        traits.set_log_dir(d);
+    }
+    traits.set_min_log_level(config.rime_log_level as u8);
+    traits.set_distribution_name(DISTRIBUTION_NAME);
+    traits.set_distribution_code_name(DISTRIBUTION_CODE_NAME);
+    traits.set_distribution_version(DISTRIBUTION_VERSION);
+    traits.set_app_name(APP_NAME);
+
+    let mut engine = Engine::new(traits);
+    let deploy_result = engine.wait_for_deploy_result(Duration::from_secs_f64(0.1));
+    match deploy_result {
+        DeployResult::Success => {
+            diagnostics::info(format_args!("Deployment succeeded"));
+        }
+        DeployResult::Failure => {
+            diagnostics::error(format_args!("Deployment failed"));
+            return Ok(None);
+        }
+    }
+    engine.create_session()?;
+    let session = engine.session().unwrap();
+    if let Some(schema) = schema {
+        session.select_schema(schema);
+    }
+    Ok(Some(engine))
+}
+
+/// Processes the key sequences in `script` without the TUI, and prints the output to stdout
+fn run_script(engine: &Engine, script: &str) -> anyhow::Result<()> {
+    let reader: Box<dyn BufRead> = if script == "-" {
+        Box::new(stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(script)?))
+    };
+
+    let session = engine.session().unwrap();
+    let mut ui_data = UiData::default();
+    for line in reader.lines() {
+        for ke in parse_key_sequence(&line?)? {
+            process_key(session, &mut ui_data, ke);
+        }
+    }
+    print!("{}", ui_data.output.as_str());
     Ok(())
 }
 
@@ -0,0 +1,290 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::text_buffer::TextBuffer;
+
+const SESSION_PREFIX: &str = "session-";
+const EDIT_PREFIX: &str = "edit-";
+
+/// Autosaves the output to a recovery file, so it survives crashes.
+///
+/// Every session has its own file in the recovery directory, so concurrent sessions
+/// don't overwrite each other's: `session-<pid>`, or `edit-<path>` for `--edit`, with the
+/// `/`s of the edited file's absolute path replaced by `%`.
+///
+/// The file is removed on a normal exit; if it still exists on startup,
+/// the previous session didn't end normally.
+pub struct Recovery {
+    path: PathBuf,
+    saved_revision: u64,
+}
+
+impl Recovery {
+    /// The recovery file of this process
+    pub fn for_session(dir: &Path) -> Self {
+        Self::new(dir.join(format!("{}{}", SESSION_PREFIX, std::process::id())))
+    }
+
+    /// The recovery file of editing `file`, so an unfinished edit is recovered
+    /// the next time the same file is edited
+    pub fn for_edit(dir: &Path, file: &Path) -> io::Result<Self> {
+        let file = std::env::current_dir()?.join(file);
+        // resolve the directory only, as the file itself may not exist yet
+        let file = match (file.parent().map(fs::canonicalize), file.file_name()) {
+            (Some(Ok(dir)), Some(name)) => dir.join(name),
+            _ => file,
+        };
+        let name = file.to_string_lossy().replace('/', "%");
+        Ok(Self::new(dir.join(format!("{}{}", EDIT_PREFIX, name))))
+    }
+
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            saved_revision: 0,
+        }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Returns the text left in this recovery file, if any
+    pub fn load(&self) -> io::Result<Option<String>> {
+        read_non_empty(&self.path)
+    }
+
+    /// Whether this recovery file was written after `file` was last modified.
+    /// If `file` doesn't exist, it counts as older.
+    pub fn is_newer_than(&self, file: &Path) -> io::Result<bool> {
+        let file_modified = match fs::metadata(file) {
+            Ok(m) => m.modified()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        };
+        Ok(fs::metadata(&self.path)?.modified()? > file_modified)
+    }
+
+    /// Finds the newest recovery file left by a session whose process is gone,
+    /// and returns its path and text. Empty ones are removed on the way.
+    pub fn find_orphaned(dir: &Path) -> io::Result<Option<(PathBuf, String)>> {
+        let mut newest: Option<(PathBuf, SystemTime)> = None;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let pid = name
+                .to_str()
+                .and_then(|n| n.strip_prefix(SESSION_PREFIX))
+                .and_then(|pid| pid.parse::<libc::pid_t>().ok());
+            let Some(pid) = pid else {
+                continue;
+            };
+            if pid as u32 == std::process::id() || process_exists(pid) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.len() == 0 {
+                fs::remove_file(entry.path())?;
+                continue;
+            }
+            let modified = metadata.modified()?;
+            if newest.as_ref().is_none_or(|(_, m)| modified > *m) {
+                newest = Some((entry.path(), modified));
+            }
+        }
+        let Some((path, _)) = newest else {
+            return Ok(None);
+        };
+        match read_non_empty(&path)? {
+            Some(text) => Ok(Some((path, text))),
+            None => {
+                fs::remove_file(&path)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Writes the text of `buffer`, unless it hasn't changed since the last save.
+    ///
+    /// The text goes to a temporary file first, which then replaces the recovery file,
+    /// so a crash while writing can't leave a truncated recovery file.
+    pub fn save_if_changed(&mut self, buffer: &TextBuffer) -> io::Result<()> {
+        if buffer.revision() == self.saved_revision {
+            return Ok(());
+        }
+        let temp = temp_path(&self.path);
+        fs::write(&temp, buffer.as_str())?;
+        fs::rename(&temp, &self.path)?;
+        self.saved_revision = buffer.revision();
+        Ok(())
+    }
+
+    pub fn remove(self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            r => r,
+        }
+    }
+}
+
+/// `.<name>.tmp` beside `path`, which isn't taken for a recovery file
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn read_non_empty(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(text) if !text.is_empty() => Ok(Some(text)),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn process_exists(pid: libc::pid_t) -> bool {
+    // signal 0 only checks whether the process can be signaled
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::process::Command;
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Writes a recovery file named `name` in `dir`, last written `age` ago
+    fn old_file(dir: &Path, name: &str, text: &str, age: Duration) {
+        fs::write(dir.join(name), text).unwrap();
+        let file = File::options().write(true).open(dir.join(name)).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    /// The pid of a process that has exited
+    fn exited_pid() -> u32 {
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        child.id()
+    }
+
+    #[test]
+    fn finds_the_newest_orphaned_file() {
+        let dir = tempdir().unwrap();
+        let minute = Duration::from_secs(60);
+        let dead = exited_pid();
+        let live = std::os::unix::process::parent_id();
+        let own = std::process::id();
+        old_file(dir.path(), &format!("session-{}", dead), "old", minute * 3);
+        old_file(dir.path(), "session-999999999", "older", minute * 5);
+        old_file(dir.path(), &format!("session-{}", live), "live", minute);
+        old_file(dir.path(), &format!("session-{}", own), "own", minute);
+        old_file(dir.path(), "edit-%tmp%notes.txt", "edit", Duration::ZERO);
+
+        let (path, text) = Recovery::find_orphaned(dir.path()).unwrap().unwrap();
+        assert_eq!(path, dir.path().join(format!("session-{}", dead)));
+        assert_eq!(text, "old");
+    }
+
+    #[test]
+    fn removes_empty_orphaned_files() {
+        let dir = tempdir().unwrap();
+        let minute = Duration::from_secs(60);
+        old_file(dir.path(), "session-999999998", "text", minute * 2);
+        old_file(dir.path(), "session-999999999", "", minute);
+
+        let (path, _) = Recovery::find_orphaned(dir.path()).unwrap().unwrap();
+        assert_eq!(path, dir.path().join("session-999999998"));
+        assert_eq!(files(dir.path()), ["session-999999998"]);
+
+        fs::remove_file(path).unwrap();
+        old_file(dir.path(), "session-999999999", "", minute);
+        assert!(Recovery::find_orphaned(dir.path()).unwrap().is_none());
+        assert!(files(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn names_files_by_session_and_edited_file() {
+        let dir = tempdir().unwrap();
+        let session = Recovery::for_session(dir.path());
+        assert_eq!(
+            session.path(),
+            &dir.path().join(format!("session-{}", std::process::id()))
+        );
+
+        let edited = tempdir().unwrap();
+        let edited_dir = fs::canonicalize(edited.path()).unwrap();
+        // the file itself doesn't need to exist
+        let edit = Recovery::for_edit(dir.path(), &edited.path().join("a.txt")).unwrap();
+        let name = edited_dir.join("a.txt").to_str().unwrap().replace('/', "%");
+        assert_eq!(edit.path(), &dir.path().join(format!("edit-{}", name)));
+    }
+
+    #[test]
+    fn compares_with_the_edited_file() {
+        let dir = tempdir().unwrap();
+        let minute = Duration::from_secs(60);
+        let edited = dir.path().join("a.txt");
+        let recovery = Recovery::for_edit(dir.path(), &edited).unwrap();
+        let name = recovery.path().file_name().unwrap().to_str().unwrap();
+
+        old_file(dir.path(), name, "recovered", minute);
+        assert!(recovery.is_newer_than(&edited).unwrap());
+        old_file(dir.path(), "a.txt", "older", minute * 2);
+        assert!(recovery.is_newer_than(&edited).unwrap());
+        old_file(dir.path(), "a.txt", "written since", Duration::ZERO);
+        assert!(!recovery.is_newer_than(&edited).unwrap());
+    }
+
+    #[test]
+    fn saves_changed_text_only() {
+        let dir = tempdir().unwrap();
+        let mut recovery = Recovery::for_session(dir.path());
+        let mut buffer = TextBuffer::default();
+
+        // an unchanged new buffer isn't worth a file
+        recovery.save_if_changed(&buffer).unwrap();
+        assert!(files(dir.path()).is_empty());
+
+        buffer.insert_str("abc");
+        recovery.save_if_changed(&buffer).unwrap();
+        assert_eq!(recovery.load().unwrap().as_deref(), Some("abc"));
+
+        // not written again until the text changes
+        fs::write(recovery.path(), "changed elsewhere").unwrap();
+        recovery.save_if_changed(&buffer).unwrap();
+        assert_eq!(
+            recovery.load().unwrap().as_deref(),
+            Some("changed elsewhere")
+        );
+        buffer.insert('d');
+        recovery.save_if_changed(&buffer).unwrap();
+        assert_eq!(recovery.load().unwrap().as_deref(), Some("abcd"));
+
+        // no temporary file is left behind
+        assert_eq!(
+            files(dir.path()),
+            [format!("session-{}", std::process::id())]
+        );
+        recovery.remove().unwrap();
+        assert!(files(dir.path()).is_empty());
+    }
+}
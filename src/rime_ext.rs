@@ -0,0 +1,151 @@
+//! Additions to `rime-api`, and Rime APIs it doesn't wrap, called through `librime-sys`
+
+use std::ffi::CStr;
+use std::mem::MaybeUninit;
+use std::os::raw::c_char;
+
+use librime_sys::{RimeFreeSchemaList, RimeGetSchemaList, RimeSchemaList};
+use rime_api::{Session, Status};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaInfo {
+    pub id: String,
+    pub name: String,
+}
+
+/// Schemas of the deployed schema list (`schema_list` in default.yaml)
+pub fn schema_list() -> Vec<SchemaInfo> {
+    unsafe {
+        let mut list = MaybeUninit::<RimeSchemaList>::zeroed().assume_init();
+        if RimeGetSchemaList(&mut list) == 0 {
+            return Vec::new();
+        }
+        let schemas = (0..list.size)
+            .map(|i| {
+                let item = &*list.list.add(i);
+                SchemaInfo {
+                    id: to_string(item.schema_id),
+                    name: to_string(item.name),
+                }
+            })
+            .collect();
+        RimeFreeSchemaList(&mut list);
+        schemas
+    }
+}
+
+/// The state of a session, copied from [`Session::status`]
+#[derive(Debug, Clone, Default)]
+pub struct SessionStatus {
+    pub schema: SchemaInfo,
+    pub is_disabled: bool,
+    pub is_composing: bool,
+    pub is_ascii_mode: bool,
+    pub is_full_shape: bool,
+    pub is_simplified: bool,
+    pub is_traditional: bool,
+    pub is_ascii_punct: bool,
+}
+
+impl From<&Status<'_>> for SessionStatus {
+    fn from(status: &Status) -> Self {
+        Self {
+            schema: SchemaInfo {
+                id: status.schema_id.into(),
+                name: status.schema_name.into(),
+            },
+            is_disabled: status.is_disabled,
+            is_composing: status.is_composing,
+            is_ascii_mode: status.is_ascii_mode,
+            is_full_shape: status.is_full_shape,
+            is_simplified: status.is_simplified,
+            is_traditional: status.is_traditional,
+            is_ascii_punct: status.is_ascii_punct,
+        }
+    }
+}
+
+impl SessionStatus {
+    /// The value of a switch (option) like `ascii_mode`, if the status tells it
+    pub fn option(&self, name: &str) -> Option<bool> {
+        match name {
+            "ascii_mode" => Some(self.is_ascii_mode),
+            "full_shape" => Some(self.is_full_shape),
+            "simplification" => Some(self.is_simplified),
+            "ascii_punct" => Some(self.is_ascii_punct),
+            _ => None,
+        }
+    }
+}
+
+pub trait SessionExt {
+    /// [`Session::status`] as a [`SessionStatus`]. Returns `None` if the session has gone.
+    fn status_ext(&self) -> Option<SessionStatus>;
+
+    /// Toggles a switch (option) like `ascii_mode` by simulating `keys`, a key sequence
+    /// Rime's key binder toggles it with, like `{Control+period}` for `ascii_punct`.
+    ///
+    /// Returns the value afterwards, if the status tells it.
+    fn toggle_option(&self, name: &str, keys: &str) -> rime_api::errors::Result<Option<bool>>;
+
+    /// Selects the `index`-th candidate of the current page by pressing its select key,
+    /// which works whatever the layout of the candidates.
+    /// Returns false if there's no such candidate, or no key for it.
+    fn select_candidate_on_current_page(&self, index: usize) -> bool;
+}
+
+impl SessionExt for Session {
+    fn status_ext(&self) -> Option<SessionStatus> {
+        self.status().ok().map(|s| SessionStatus::from(&s))
+    }
+
+    fn toggle_option(&self, name: &str, keys: &str) -> rime_api::errors::Result<Option<bool>> {
+        self.simulate_key_sequence(keys)?;
+        Ok(self.status_ext().and_then(|s| s.option(name)))
+    }
+
+    fn select_candidate_on_current_page(&self, index: usize) -> bool {
+        let key = self.context().and_then(|context| {
+            let in_page = index < context.menu.num_candidates as usize;
+            in_page.then(|| select_key(context.select_labels.as_deref(), index))?
+        });
+        key.is_some_and(|k| self.simulate_key_sequence(&k).is_ok())
+    }
+}
+
+/// The key selecting the `index`-th candidate of a page: its label, if the labels are
+/// keys themselves, else librime's default select keys "1" to "9" and "0".
+///
+/// rime-api leaves the menu's `select_keys` empty, and labels set by a schema's
+/// `menu/alternative_select_labels` can be symbols like "①" that are no keys.
+fn select_key(labels: Option<&[&str]>, index: usize) -> Option<String> {
+    let is_key = |l: &&str| l.len() == 1 && l.bytes().all(|b| b.is_ascii_alphanumeric());
+    match labels {
+        Some(labels) if labels.iter().all(is_key) => labels.get(index).map(|&l| l.into()),
+        _ => "1234567890".get(index..index + 1).map(Into::into),
+    }
+}
+
+unsafe fn to_string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(s).to_string_lossy().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_with_labels_or_digits() {
+        assert_eq!(select_key(None, 0).as_deref(), Some("1"));
+        assert_eq!(select_key(None, 9).as_deref(), Some("0"));
+        assert_eq!(select_key(None, 10), None);
+        let keys = ["a", "s", "d"];
+        assert_eq!(select_key(Some(&keys), 1).as_deref(), Some("s"));
+        assert_eq!(select_key(Some(&keys), 3), None);
+        let symbols = ["①", "②"];
+        assert_eq!(select_key(Some(&symbols), 1).as_deref(), Some("2"));
+    }
+}
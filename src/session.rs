@@ -0,0 +1,107 @@
+use librime_sys::{RimeModifier_kControlMask, RimeModifier_kLockMask};
+use rime_api::{KeyEvent, KeyStatus, Session};
+use x11::keysym::*;
+
+use crate::rime_ext::SessionExt;
+use crate::tui::{Candidate, UiData};
+
+/// Feeds `ke` to the Rime session, and updates `ui_data` with the
+/// resulting composition, candidates and commit.
+///
+/// Keys that Rime doesn't handle fall back to some default editing behaviors on the output.
+/// Returns whether Rime handled the key.
+pub fn process_key(session: &Session, ui_data: &mut UiData, ke: KeyEvent) -> KeyStatus {
+    let key_status = session.process_key(ke);
+    if key_status == KeyStatus::Pass {
+        default_behavior(ui_data, ke);
+    }
+    update_ui_data(session, ui_data);
+    key_status
+}
+
+/// Updates `ui_data` with the composition, candidates, commit and status of the session
+pub fn update_ui_data(session: &Session, ui_data: &mut UiData) {
+    let context = session.context();
+    let menu = &context.as_ref().unwrap().menu;
+    let composition = &context.as_ref().unwrap().composition;
+    let preedit = composition.preedit.unwrap_or("");
+    let select_labels = &context.as_ref().unwrap().select_labels;
+
+    ui_data.preedit = String::from(preedit);
+    ui_data.preedit_cursor = composition.cursor_pos as usize;
+    ui_data.preedit_selection = (composition.sel_start as usize, composition.sel_end as usize);
+    let highlighted = menu
+        .candidates
+        .get(menu.highlighted_candidate_index as usize)
+        .map(|c| c.text);
+    ui_data.commit_preview = commit_preview(preedit, ui_data.preedit_selection, highlighted);
+    ui_data.candidates = menu
+        .candidates
+        .iter()
+        .enumerate()
+        .map(|(i, x)| Candidate {
+            text: x.text.into(),
+            comment: x.comment.unwrap_or("").into(),
+            highlighted: i == menu.highlighted_candidate_index as usize,
+        })
+        .collect();
+    ui_data.page_no = menu.page_no as usize;
+    ui_data.is_last_page = menu.is_last_page;
+    if let Some(l) = select_labels {
+        ui_data.select_labels = Some(l.iter().map(|x| String::from(*x)).collect::<Vec<_>>());
+    }
+    drop(context);
+    let commit = session.commit();
+    // TODO: if taking the ownership of `commit` in the `match` below,
+    //  `c.text` will be freed and thus its data is invalid
+    let commit = match &commit {
+        None => "",
+        Some(c) => c.text,
+    };
+    ui_data.output.insert_str(commit);
+    ui_data.status = session.status_ext();
+}
+
+/// The text that would be committed now: the preedit, with the segment being converted
+/// replaced by the highlighted candidate.
+///
+/// This is what librime offers as `commit_text_preview`, which rime-api's `Context` leaves out.
+fn commit_preview(
+    preedit: &str,
+    (sel_start, sel_end): (usize, usize),
+    candidate: Option<&str>,
+) -> Option<String> {
+    let converted = preedit.get(..sel_start)?;
+    let rest = preedit.get(sel_end..)?;
+    Some(format!("{}{}{}", converted, candidate?, rest))
+}
+
+#[allow(non_upper_case_globals)]
+fn default_behavior(ui_data: &mut UiData, ke: KeyEvent) {
+    let output = &mut ui_data.output;
+    // CapsLock is a state rather than a held modifier
+    match ke.modifiers & !(RimeModifier_kLockMask as i32) {
+        0 => match ke.key_code as u32 {
+            k @ XK_a..=XK_z => output.insert(char::from((k - XK_a) as u8 + b'a')),
+            k @ XK_A..=XK_Z => output.insert(char::from((k - XK_A) as u8 + b'A')),
+            k @ XK_0..=XK_9 => output.insert(char::from((k - XK_0) as u8 + b'0')),
+            XK_BackSpace => output.backspace(),
+            XK_Delete => output.delete(),
+            XK_Return => output.insert('\n'),
+            XK_space => output.insert(' '),
+            XK_Left => output.move_left(),
+            XK_Right => output.move_right(),
+            XK_Up => output.move_up(),
+            XK_Down => output.move_down(),
+            XK_Home => output.move_home(),
+            XK_End => output.move_end(),
+            _ => {}
+        },
+        m if m == RimeModifier_kControlMask as i32 => match ke.key_code as u32 {
+            XK_Left => output.move_word_left(),
+            XK_Right => output.move_word_right(),
+            _ => {}
+        },
+        _ => {}
+    }
+}
@@ -0,0 +1,269 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crossterm::event::{
+    KeyCode, KeyEvent as TermKeyEvent, KeyEventKind, KeyEventState, KeyModifiers,
+    KeyboardEnhancementFlags, ModifierKeyCode, PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
+use crossterm::execute;
+use librime_sys::{
+    RimeModifier_kAltMask, RimeModifier_kControlMask, RimeModifier_kLockMask,
+    RimeModifier_kReleaseMask, RimeModifier_kShiftMask, RimeModifier_kSuperMask,
+};
+use rime_api::KeyEvent;
+use x11::keysym::*;
+
+use crate::key_event::char_to_keysym;
+use crate::tui::open_tty;
+
+/// Enables the kitty keyboard protocol while alive, so key releases and modifier keys
+/// are reported by the terminal. The key events themselves arrive with the other terminal
+/// events, and are converted by [`to_rime_key_event`].
+///
+/// This needs no X server, but the terminal must support the protocol
+/// (kitty, foot, WezTerm, etc.).
+pub struct TerminalInput;
+
+/// Whether the keyboard enhancement flags are pushed by a [`TerminalInput`]
+static ENHANCED: AtomicBool = AtomicBool::new(false);
+
+impl TerminalInput {
+    /// The keyboard enhancement flags are pushed here and popped on drop, so this
+    /// needs to be created after entering the alternate screen, and dropped before leaving it.
+    pub fn new() -> io::Result<Self> {
+        execute!(
+            open_tty()?,
+            PushKeyboardEnhancementFlags(
+                KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                    | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                    | KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS
+                    | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+            )
+        )?;
+        ENHANCED.store(true, Ordering::SeqCst);
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        pop_keyboard_enhancement();
+    }
+}
+
+/// Pops the keyboard enhancement flags, if a [`TerminalInput`] has pushed them.
+///
+/// Also for a panic hook, where the [`TerminalInput`] isn't dropped before leaving the alternate screen.
+pub fn pop_keyboard_enhancement() {
+    if !ENHANCED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    if let Ok(mut tty) = open_tty() {
+        let _ = execute!(tty, PopKeyboardEnhancementFlags);
+    }
+}
+
+pub fn to_rime_key_event(event: &TermKeyEvent) -> Option<KeyEvent> {
+    let keypad = event.state.contains(KeyEventState::KEYPAD);
+    let shift = event.modifiers.contains(KeyModifiers::SHIFT);
+
+    let code = match event.code {
+        KeyCode::Char(c) if keypad && c.is_ascii_digit() => XK_KP_0 + (c as u32 - '0' as u32),
+        KeyCode::Char(c) if shift && c.is_ascii_lowercase() => c.to_ascii_uppercase() as u32,
+        KeyCode::Char(' ') => XK_space,
+        KeyCode::Char(c) => char_to_keysym(c),
+        KeyCode::Enter if keypad => XK_KP_Enter,
+        KeyCode::Enter => XK_Return,
+        KeyCode::Backspace => XK_BackSpace,
+        KeyCode::Left => XK_Left,
+        KeyCode::Right => XK_Right,
+        KeyCode::Up => XK_Up,
+        KeyCode::Down => XK_Down,
+        KeyCode::Home => XK_Home,
+        KeyCode::End => XK_End,
+        KeyCode::PageUp => XK_Page_Up,
+        KeyCode::PageDown => XK_Page_Down,
+        KeyCode::Tab => XK_Tab,
+        KeyCode::BackTab => XK_ISO_Left_Tab,
+        KeyCode::Delete => XK_Delete,
+        KeyCode::Insert => XK_Insert,
+        KeyCode::F(n @ 1..=24) => XK_F1 + (n as u32 - 1),
+        KeyCode::Esc => XK_Escape,
+        KeyCode::CapsLock => XK_Caps_Lock,
+        KeyCode::ScrollLock => XK_Scroll_Lock,
+        KeyCode::NumLock => XK_Num_Lock,
+        KeyCode::PrintScreen => XK_Print,
+        KeyCode::Pause => XK_Pause,
+        KeyCode::Menu => XK_Menu,
+        KeyCode::Modifier(m) => match m {
+            ModifierKeyCode::LeftShift => XK_Shift_L,
+            ModifierKeyCode::RightShift => XK_Shift_R,
+            ModifierKeyCode::LeftControl => XK_Control_L,
+            ModifierKeyCode::RightControl => XK_Control_R,
+            ModifierKeyCode::LeftAlt => XK_Alt_L,
+            ModifierKeyCode::RightAlt => XK_Alt_R,
+            ModifierKeyCode::LeftSuper => XK_Super_L,
+            ModifierKeyCode::RightSuper => XK_Super_R,
+            ModifierKeyCode::LeftMeta => XK_Meta_L,
+            ModifierKeyCode::RightMeta => XK_Meta_R,
+            ModifierKeyCode::LeftHyper => XK_Hyper_L,
+            ModifierKeyCode::RightHyper => XK_Hyper_R,
+            ModifierKeyCode::IsoLevel3Shift => XK_ISO_Level3_Shift,
+            ModifierKeyCode::IsoLevel5Shift => XK_ISO_Level5_Shift,
+        },
+        _ => return None,
+    };
+
+    let mut ke = KeyEvent {
+        key_code: code as i32,
+        modifiers: 0,
+    };
+    if event.kind == KeyEventKind::Release {
+        ke.modifiers |= RimeModifier_kReleaseMask as i32;
+    }
+    if shift {
+        ke.modifiers |= RimeModifier_kShiftMask as i32;
+    }
+    if event.modifiers.contains(KeyModifiers::SUPER) {
+        ke.modifiers |= RimeModifier_kSuperMask as i32;
+    }
+    if event.modifiers.contains(KeyModifiers::CONTROL) {
+        ke.modifiers |= RimeModifier_kControlMask as i32;
+    }
+    if event.modifiers.contains(KeyModifiers::ALT) {
+        ke.modifiers |= RimeModifier_kAltMask as i32;
+    }
+    if event.state.contains(KeyEventState::CAPS_LOCK) {
+        ke.modifiers |= RimeModifier_kLockMask as i32;
+    }
+    Some(ke)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHIFT: u32 = RimeModifier_kShiftMask;
+    const CONTROL: u32 = RimeModifier_kControlMask;
+    const ALT: u32 = RimeModifier_kAltMask;
+    const SUPER: u32 = RimeModifier_kSuperMask;
+    const RELEASE: u32 = RimeModifier_kReleaseMask;
+    const LOCK: u32 = RimeModifier_kLockMask;
+
+    fn convert(
+        code: KeyCode,
+        modifiers: KeyModifiers,
+        kind: KeyEventKind,
+        state: KeyEventState,
+    ) -> Option<(u32, u32)> {
+        let event = TermKeyEvent::new_with_kind_and_state(code, modifiers, kind, state);
+        to_rime_key_event(&event).map(|ke| (ke.key_code as u32, ke.modifiers as u32))
+    }
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> Option<(u32, u32)> {
+        convert(code, modifiers, KeyEventKind::Press, KeyEventState::NONE)
+    }
+
+    #[test]
+    fn characters() {
+        let none = KeyModifiers::NONE;
+        for (code, expected) in [
+            (KeyCode::Char('a'), (XK_a, 0)),
+            (KeyCode::Char(' '), (XK_space, 0)),
+            (KeyCode::Char('1'), (XK_1, 0)),
+            (KeyCode::Char(','), (XK_comma, 0)),
+            (KeyCode::Char('é'), (XK_eacute, 0)),
+            (KeyCode::Char('你'), (0x0100_4f60, 0)),
+        ] {
+            assert_eq!(press(code, none), Some(expected), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn shifted_characters() {
+        let shift = KeyModifiers::SHIFT;
+        for (code, expected) in [
+            // the terminal may report either the base or the shifted letter
+            (KeyCode::Char('a'), (XK_A, SHIFT)),
+            (KeyCode::Char('A'), (XK_A, SHIFT)),
+            (KeyCode::Char('!'), (XK_exclam, SHIFT)),
+            (KeyCode::Char('@'), (XK_at, SHIFT)),
+            (KeyCode::Char(':'), (XK_colon, SHIFT)),
+        ] {
+            assert_eq!(press(code, shift), Some(expected), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn modifiers() {
+        for (modifiers, expected) in [
+            (KeyModifiers::CONTROL, CONTROL),
+            (KeyModifiers::ALT, ALT),
+            (KeyModifiers::SUPER, SUPER),
+            (KeyModifiers::CONTROL | KeyModifiers::ALT, CONTROL | ALT),
+            (KeyModifiers::CONTROL | KeyModifiers::SHIFT, CONTROL | SHIFT),
+        ] {
+            assert_eq!(
+                press(KeyCode::Char('x'), modifiers).map(|(_, m)| m),
+                Some(expected),
+                "{:?}",
+                modifiers
+            );
+        }
+        assert_eq!(
+            convert(
+                KeyCode::Char('x'),
+                KeyModifiers::NONE,
+                KeyEventKind::Release,
+                KeyEventState::CAPS_LOCK
+            ),
+            Some((XK_x, RELEASE | LOCK))
+        );
+        assert_eq!(
+            convert(
+                KeyCode::Modifier(ModifierKeyCode::LeftControl),
+                KeyModifiers::CONTROL,
+                KeyEventKind::Release,
+                KeyEventState::NONE
+            ),
+            Some((XK_Control_L, RELEASE | CONTROL))
+        );
+    }
+
+    #[test]
+    fn function_and_editing_keys() {
+        let none = KeyModifiers::NONE;
+        for (code, expected) in [
+            (KeyCode::F(1), XK_F1),
+            (KeyCode::F(12), XK_F12),
+            (KeyCode::F(24), XK_F24),
+            (KeyCode::Enter, XK_Return),
+            (KeyCode::Backspace, XK_BackSpace),
+            (KeyCode::Esc, XK_Escape),
+            (KeyCode::Tab, XK_Tab),
+            (KeyCode::BackTab, XK_ISO_Left_Tab),
+            (KeyCode::PageDown, XK_Page_Down),
+            (KeyCode::Modifier(ModifierKeyCode::RightSuper), XK_Super_R),
+        ] {
+            assert_eq!(press(code, none), Some((expected, 0)), "{:?}", code);
+        }
+        assert_eq!(press(KeyCode::F(25), none), None);
+        assert_eq!(press(KeyCode::Null, none), None);
+    }
+
+    #[test]
+    fn keypad_keys() {
+        let keypad = |code| {
+            convert(
+                code,
+                KeyModifiers::NONE,
+                KeyEventKind::Press,
+                KeyEventState::KEYPAD,
+            )
+        };
+        assert_eq!(keypad(KeyCode::Char('0')), Some((XK_KP_0, 0)));
+        assert_eq!(keypad(KeyCode::Char('7')), Some((XK_KP_7, 0)));
+        assert_eq!(keypad(KeyCode::Enter), Some((XK_KP_Enter, 0)));
+    }
+}
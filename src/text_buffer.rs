@@ -0,0 +1,512 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// Maximum number of undo steps kept
+const HISTORY_LIMIT: usize = 500;
+
+/// A text buffer with a cursor, used for the output
+///
+/// Every call of an editing method (e.g. inserting a whole Rime commit) is one undo step,
+/// except that consecutive typed chars, backspaces or deletes are merged into one.
+#[derive(Debug, Default, Clone)]
+pub struct TextBuffer {
+    text: String,
+    /// Byte index into `text`, always on a char boundary
+    cursor: usize,
+    undo_stack: VecDeque<Edit>,
+    redo_stack: Vec<Edit>,
+    /// Incremented on every change of the text
+    revision: u64,
+}
+
+/// An undo step: `removed` at `at` was replaced by `inserted`
+#[derive(Debug, Clone)]
+struct Edit {
+    kind: EditKind,
+    at: usize,
+    removed: String,
+    inserted: String,
+    /// The cursor before the edit
+    cursor: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EditKind {
+    /// A char typed with [`TextBuffer::insert`]
+    Typing,
+    Backspace,
+    Delete,
+    Other,
+}
+
+impl Edit {
+    /// Extends this step with `next` if both are the same kind of single-char edits
+    /// right next to each other, e.g. typing "ab" after "a". Typing stops merging after whitespace.
+    fn merge(&mut self, next: &Edit) -> bool {
+        if self.kind != next.kind {
+            return false;
+        }
+        match self.kind {
+            EditKind::Typing
+                if next.at == self.at + self.inserted.len()
+                    && !self.inserted.ends_with(char::is_whitespace) =>
+            {
+                self.inserted.push_str(&next.inserted);
+            }
+            EditKind::Backspace if next.at + next.removed.len() == self.at => {
+                self.removed.insert_str(0, &next.removed);
+                self.at = next.at;
+            }
+            EditKind::Delete if next.at == self.at => {
+                self.removed.push_str(&next.removed);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[derive(PartialEq)]
+enum CharClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+impl CharClass {
+    fn of(c: char) -> Self {
+        if c.is_whitespace() {
+            Self::Space
+        } else if c.is_alphanumeric() || c == '_' {
+            Self::Word
+        } else {
+            Self::Punctuation
+        }
+    }
+}
+
+impl TextBuffer {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Replaces the whole text, and puts the cursor at the end
+    pub fn set_text(&mut self, text: String) {
+        self.edit(EditKind::Other, 0..self.text.len(), &text);
+    }
+
+    /// Replaces the whole text like [`Self::set_text`], but as a new buffer:
+    /// with no undo step, and the undo history cleared
+    pub fn reset(&mut self, text: String) {
+        self.text = text;
+        self.cursor = self.text.len();
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.revision += 1;
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.edit(
+            EditKind::Typing,
+            self.cursor..self.cursor,
+            c.encode_utf8(&mut [0; 4]),
+        );
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        self.edit(EditKind::Other, self.cursor..self.cursor, s);
+    }
+
+    /// Deletes the char before the cursor
+    pub fn backspace(&mut self) {
+        if let Some(c) = self.char_before() {
+            self.edit(
+                EditKind::Backspace,
+                self.cursor - c.len_utf8()..self.cursor,
+                "",
+            );
+        }
+    }
+
+    /// Deletes the char after the cursor
+    pub fn delete(&mut self) {
+        if let Some(c) = self.char_after() {
+            self.edit(
+                EditKind::Delete,
+                self.cursor..self.cursor + c.len_utf8(),
+                "",
+            );
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        if let Some(c) = self.char_before() {
+            self.cursor -= c.len_utf8();
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if let Some(c) = self.char_after() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    /// Moves to the start of the whole text
+    pub fn move_to_start(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Moves to the start of the current line
+    pub fn move_home(&mut self) {
+        self.cursor = self.line_start(self.cursor);
+    }
+
+    /// Moves to the end of the current line
+    pub fn move_end(&mut self) {
+        self.cursor = self.line_end(self.cursor);
+    }
+
+    pub fn move_up(&mut self) {
+        let start = self.line_start(self.cursor);
+        if start == 0 {
+            self.cursor = 0;
+            return;
+        }
+        let column = self.text[start..self.cursor].chars().count();
+        let prev_start = self.line_start(start - 1);
+        self.cursor = self.index_in_line(prev_start, column);
+    }
+
+    pub fn move_down(&mut self) {
+        let end = self.line_end(self.cursor);
+        if end == self.text.len() {
+            self.cursor = end;
+            return;
+        }
+        let column = self.text[self.line_start(self.cursor)..self.cursor]
+            .chars()
+            .count();
+        self.cursor = self.index_in_line(end + 1, column);
+    }
+
+    /// Moves to the start of the previous word
+    pub fn move_word_left(&mut self) {
+        while self.char_before().map(CharClass::of) == Some(CharClass::Space) {
+            self.move_left();
+        }
+        if let Some(class) = self.char_before().map(CharClass::of) {
+            while self.char_before().map(CharClass::of).as_ref() == Some(&class) {
+                self.move_left();
+            }
+        }
+    }
+
+    /// Moves to the end of the next word
+    pub fn move_word_right(&mut self) {
+        while self.char_after().map(CharClass::of) == Some(CharClass::Space) {
+            self.move_right();
+        }
+        if let Some(class) = self.char_after().map(CharClass::of) {
+            while self.char_after().map(CharClass::of).as_ref() == Some(&class) {
+                self.move_right();
+            }
+        }
+    }
+
+    /// Returns false if there's nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let Some(edit) = self.undo_stack.pop_back() else {
+            return false;
+        };
+        let range = edit.at..edit.at + edit.inserted.len();
+        self.text.replace_range(range, &edit.removed);
+        self.cursor = edit.cursor;
+        self.revision += 1;
+        self.redo_stack.push(edit);
+        // typing after an undo starts a new step
+        if let Some(last) = self.undo_stack.back_mut() {
+            last.kind = EditKind::Other;
+        }
+        true
+    }
+
+    /// Returns false if there's nothing to redo
+    pub fn redo(&mut self) -> bool {
+        let Some(edit) = self.redo_stack.pop() else {
+            return false;
+        };
+        let range = edit.at..edit.at + edit.removed.len();
+        self.text.replace_range(range, &edit.inserted);
+        self.cursor = edit.at + edit.inserted.len();
+        self.revision += 1;
+        self.undo_stack.push_back(Edit {
+            // a redone step is never extended
+            kind: EditKind::Other,
+            ..edit
+        });
+        true
+    }
+
+    /// Replaces `range` of the text with `inserted`, puts the cursor after it,
+    /// and records the change as an undo step, or merges it into the last one
+    fn edit(&mut self, kind: EditKind, range: Range<usize>, inserted: &str) {
+        let removed = self.text[range.clone()].to_string();
+        let edit = Edit {
+            kind,
+            at: range.start,
+            removed,
+            inserted: inserted.into(),
+            cursor: self.cursor,
+        };
+        self.text.replace_range(range, inserted);
+        self.cursor = edit.at + edit.inserted.len();
+        self.revision += 1;
+        self.redo_stack.clear();
+
+        if let Some(last) = self.undo_stack.back_mut() {
+            if last.merge(&edit) {
+                return;
+            }
+        }
+        if self.undo_stack.len() == HISTORY_LIMIT {
+            self.undo_stack.pop_front();
+        }
+        self.undo_stack.push_back(edit);
+    }
+
+    fn char_before(&self) -> Option<char> {
+        self.text[..self.cursor].chars().next_back()
+    }
+
+    fn char_after(&self) -> Option<char> {
+        self.text[self.cursor..].chars().next()
+    }
+
+    fn line_start(&self, index: usize) -> usize {
+        self.text[..index].rfind('\n').map(|i| i + 1).unwrap_or(0)
+    }
+
+    fn line_end(&self, index: usize) -> usize {
+        self.text[index..]
+            .find('\n')
+            .map(|i| index + i)
+            .unwrap_or(self.text.len())
+    }
+
+    /// Byte index of the `column`-th char of the line starting at `line_start`,
+    /// or the line end if the line is shorter
+    fn index_in_line(&self, line_start: usize, column: usize) -> usize {
+        let line_end = self.line_end(line_start);
+        self.text[line_start..line_end]
+            .char_indices()
+            .nth(column)
+            .map(|(i, _)| line_start + i)
+            .unwrap_or(line_end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A buffer containing `text`, with the cursor at the `|` in it
+    fn buffer(text: &str) -> TextBuffer {
+        TextBuffer {
+            text: text.replacen('|', "", 1),
+            cursor: text.find('|').unwrap(),
+            ..Default::default()
+        }
+    }
+
+    /// The text with a `|` at the cursor
+    fn show(buffer: &TextBuffer) -> String {
+        let mut text = buffer.as_str().to_string();
+        text.insert(buffer.cursor(), '|');
+        text
+    }
+
+    #[test]
+    fn insert_and_delete() {
+        let mut b = buffer("ab|c");
+        b.insert('你');
+        b.insert_str("好x");
+        assert_eq!(show(&b), "ab你好x|c");
+        b.backspace();
+        b.backspace();
+        assert_eq!(show(&b), "ab你|c");
+        b.delete();
+        b.delete();
+        assert_eq!(show(&b), "ab你|");
+        b.move_to_start();
+        b.backspace();
+        assert_eq!(show(&b), "|ab你");
+    }
+
+    #[test]
+    fn set_text_moves_to_end() {
+        let mut b = buffer("|");
+        b.set_text("一\n二".into());
+        assert_eq!(show(&b), "一\n二|");
+    }
+
+    #[test]
+    fn move_left_right_over_multibyte_chars() {
+        let mut b = buffer("a你|b");
+        b.move_left();
+        assert_eq!(show(&b), "a|你b");
+        b.move_left();
+        b.move_left();
+        assert_eq!(show(&b), "|a你b");
+        b.move_right();
+        b.move_right();
+        b.move_right();
+        b.move_right();
+        assert_eq!(show(&b), "a你b|");
+    }
+
+    #[test]
+    fn move_within_lines() {
+        let mut b = buffer("abc\nd|ef\n");
+        b.move_home();
+        assert_eq!(show(&b), "abc\n|def\n");
+        b.move_end();
+        assert_eq!(show(&b), "abc\ndef|\n");
+    }
+
+    #[test]
+    fn move_up_down_keeps_the_column_in_chars() {
+        let mut b = buffer("你好吗\nab|c\nd");
+        b.move_up();
+        assert_eq!(show(&b), "你好|吗\nabc\nd");
+        b.move_up();
+        assert_eq!(show(&b), "|你好吗\nabc\nd");
+
+        let mut b = buffer("abc\nab|c\nd");
+        b.move_down();
+        assert_eq!(show(&b), "abc\nabc\nd|");
+        b.move_down();
+        assert_eq!(show(&b), "abc\nabc\nd|");
+    }
+
+    #[test]
+    fn move_by_words() {
+        let mut b = buffer("foo_bar, baz|");
+        b.move_word_left();
+        assert_eq!(show(&b), "foo_bar, |baz");
+        b.move_word_left();
+        assert_eq!(show(&b), "foo_bar|, baz");
+        b.move_word_left();
+        assert_eq!(show(&b), "|foo_bar, baz");
+        b.move_word_left();
+        assert_eq!(show(&b), "|foo_bar, baz");
+
+        b.move_word_right();
+        assert_eq!(show(&b), "foo_bar|, baz");
+        b.move_word_right();
+        assert_eq!(show(&b), "foo_bar,| baz");
+        b.move_word_right();
+        assert_eq!(show(&b), "foo_bar, baz|");
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut b = buffer("a|");
+        b.insert_str("你好");
+        b.set_text("loaded".into());
+        assert_eq!(show(&b), "loaded|");
+        assert!(b.undo());
+        assert_eq!(show(&b), "a你好|");
+        assert!(b.undo());
+        assert_eq!(show(&b), "a|");
+        assert!(b.redo());
+        assert_eq!(show(&b), "a你好|");
+        b.backspace();
+        assert!(!b.redo());
+        assert!(b.undo());
+        assert_eq!(show(&b), "a你好|");
+    }
+
+    #[test]
+    fn consecutive_typing_is_one_step() {
+        let mut b = buffer("|");
+        b.insert_str("你");
+        for c in "ab cd".chars() {
+            b.insert(c);
+        }
+        assert_eq!(show(&b), "你ab cd|");
+        b.undo();
+        assert_eq!(show(&b), "你ab |");
+        b.undo();
+        assert_eq!(show(&b), "你|");
+        b.undo();
+        assert_eq!(show(&b), "|");
+        assert!(!b.undo());
+    }
+
+    #[test]
+    fn consecutive_deletes_are_one_step() {
+        let mut b = buffer("abc|def");
+        b.backspace();
+        b.backspace();
+        b.delete();
+        b.delete();
+        assert_eq!(show(&b), "a|f");
+        b.undo();
+        assert_eq!(show(&b), "a|def");
+        b.undo();
+        assert_eq!(show(&b), "abc|def");
+    }
+
+    #[test]
+    fn typing_after_undo_starts_a_new_step() {
+        let mut b = buffer("|");
+        b.insert('a');
+        b.insert_str("你");
+        b.undo();
+        b.insert('b');
+        b.undo();
+        assert_eq!(show(&b), "a|");
+    }
+
+    #[test]
+    fn history_is_limited() {
+        let mut b = buffer("|");
+        for _ in 0..HISTORY_LIMIT + 10 {
+            b.insert_str("x");
+        }
+        while b.undo() {}
+        assert_eq!(b.as_str().len(), 10);
+    }
+
+    #[test]
+    fn reset_clears_the_history() {
+        let mut b = buffer("|");
+        b.insert_str("a");
+        b.undo();
+        b.reset("loaded".into());
+        assert_eq!(show(&b), "loaded|");
+        assert!(!b.undo());
+        assert!(!b.redo());
+    }
+
+    #[test]
+    fn revision_changes_with_the_text_only() {
+        let mut b = buffer("ab|");
+        let revision = b.revision();
+        b.move_left();
+        assert_eq!(b.revision(), revision);
+        b.insert('c');
+        assert_ne!(b.revision(), revision);
+    }
+}
@@ -1,25 +1,134 @@
+use std::fs::File;
 use std::io;
-use std::io::{stdout, Stdout};
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
 use std::thread::spawn;
 
-use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::event::{
+    DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture, Event,
+};
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
-use crossterm::{event, execute};
+use crossterm::{cursor, event, execute};
 use tui::backend::{Backend, CrosstermBackend};
-use tui::layout::{Constraint, Direction, Layout};
-use tui::style::{Color, Style};
-use tui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph};
 use tui::{Frame, Terminal};
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+use crate::config::{CandidateLayout, LayoutConfig, Pane, Side, ThemeConfig};
+use crate::glog::{LogEntry, Severity};
+use crate::log_buffer::LogBuffer;
+use crate::rime_ext::{SchemaInfo, SessionStatus};
+use crate::text_buffer::TextBuffer;
+
 pub struct TuiApp<B>
 where
     B: Backend,
 {
     pub ui_data: UiData,
     terminal: Terminal<B>,
+    theme: Theme,
+    layout: LayoutConfig,
+    click_targets: Vec<(Rect, ClickTarget)>,
+}
+
+/// What a mouse click on an area of the UI does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClickTarget {
+    /// Toggles a Rime option like `ascii_mode`
+    ToggleOption(&'static str),
+    /// The index of a candidate on the current page
+    Candidate(usize),
+    /// Anywhere in the Candidates pane; scrolling here turns pages
+    Candidates,
+    /// The Log pane or the log viewer, which scroll
+    Log,
+    /// The warning and error counts in the status bar; clicking opens the log viewer
+    LogBadge,
+}
+
+/// Resolved colors of [`ThemeConfig`]
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub preedit: Color,
+    pub highlight_fg: Color,
+    pub highlight_bg: Color,
+    pub border: Color,
+    pub log_warning: Color,
+    pub log_error: Color,
+}
+
+impl Theme {
+    pub fn from_config(config: &ThemeConfig) -> io::Result<Self> {
+        let color = |name: &str| {
+            parse_color(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid color: {}", name),
+                )
+            })
+        };
+        Ok(Self {
+            preedit: color(&config.preedit)?,
+            highlight_fg: color(&config.highlight_fg)?,
+            highlight_bg: color(&config.highlight_bg)?,
+            border: color(&config.border)?,
+            log_warning: color(&config.log_warning)?,
+            log_error: color(&config.log_error)?,
+        })
+    }
+
+    fn border_style(&self) -> Style {
+        Style::default().fg(self.border)
+    }
+
+    fn log_style(&self, severity: Severity) -> Style {
+        match severity {
+            Severity::Info => Style::default(),
+            Severity::Warning => Style::default().fg(self.log_warning),
+            Severity::Error => Style::default().fg(self.log_error),
+            Severity::Fatal => Style::default()
+                .fg(self.log_error)
+                .add_modifier(Modifier::BOLD),
+        }
+    }
+}
+
+/// Parses a color name like "lightblue", or "#rrggbb"
+pub fn parse_color(name: &str) -> Option<Color> {
+    if let Some(hex) = name.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let rgb = u32::from_str_radix(hex, 16).ok()?;
+        return Some(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+    }
+    let color = match name.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    };
+    Some(color)
 }
 
 #[derive(Debug, Default)]
@@ -32,138 +141,991 @@ pub struct Candidate {
 #[derive(Debug, Default)]
 pub struct UiData {
     pub preedit: String,
+    /// Byte index of the caret in `preedit`
+    pub preedit_cursor: usize,
+    /// Byte range of the segment being converted in `preedit`; the text before it
+    /// is already converted, and the text after it is not yet
+    pub preedit_selection: (usize, usize),
+    /// The text that would be committed now, if the schema offers it
+    pub commit_preview: Option<String>,
     pub candidates: Vec<Candidate>,
-    pub output: String,
-    pub log: Vec<String>,
+    /// The current candidate page, from 0
+    pub page_no: usize,
+    pub is_last_page: bool,
+    pub output: TextBuffer,
+    pub log: LogBuffer,
+    /// Lines the Log pane is scrolled up from the newest line
+    pub log_scroll: usize,
+    /// Shown over everything while viewing the whole log
+    pub log_viewer: Option<LogViewer>,
+    /// Warnings logged since the log viewer was last opened
+    pub unseen_warnings: usize,
+    /// Errors logged since the log viewer was last opened
+    pub unseen_errors: usize,
     pub select_labels: Option<Vec<String>>,
+    /// Whether key events are currently captured
+    pub input_live: bool,
+    pub candidate_layout: CandidateLayout,
+    pub log_hidden: bool,
+    /// Hides everything but the Output, the Preedit and a candidate bar
+    pub output_maximized: bool,
+    pub status: Option<SessionStatus>,
+    /// Shown over the other panes while choosing a schema
+    pub schema_picker: Option<SchemaPicker>,
+    /// Shown in place of the preedit while typing the argument of a command
+    pub argument_prompt: Option<ArgumentPrompt>,
+}
+
+impl UiData {
+    /// Appends a log entry, keeping the Log pane and the log viewer at the lines they show
+    pub fn push_log(&mut self, entry: LogEntry) {
+        let visible = self.log.is_visible(&entry);
+        match entry.severity {
+            _ if self.log_viewer.is_some() => {}
+            Severity::Info => {}
+            Severity::Warning => self.unseen_warnings += 1,
+            Severity::Error | Severity::Fatal => self.unseen_errors += 1,
+        }
+        let dropped = self.log.push(entry);
+        if visible && self.log_scroll > 0 {
+            self.log_scroll += 1;
+        }
+        if let Some(v) = &mut self.log_viewer {
+            if visible && v.scroll > 0 {
+                v.scroll += 1;
+            }
+            if dropped {
+                v.current_match = v.current_match.and_then(|i| i.checked_sub(1));
+            }
+        }
+    }
+
+    pub fn open_log_viewer(&mut self) {
+        self.log_viewer = Some(LogViewer::new(self.log_scroll));
+        self.unseen_warnings = 0;
+        self.unseen_errors = 0;
+    }
+
+    /// Only shows log entries at or above `severity`
+    pub fn set_log_severity(&mut self, severity: Severity) {
+        self.log.set_min_severity(severity);
+        // the line indices have changed
+        self.log_scroll = 0;
+        if let Some(v) = &mut self.log_viewer {
+            v.scroll = 0;
+            v.current_match = None;
+        }
+    }
+
+    /// Clears what's shown of the Rime composition
+    pub fn clear_composition(&mut self) {
+        self.preedit.clear();
+        self.preedit_cursor = 0;
+        self.preedit_selection = (0, 0);
+        self.commit_preview = None;
+        self.candidates.clear();
+        self.page_no = 0;
+        self.is_last_page = false;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LogViewer {
+    /// Lines scrolled up from the newest line
+    pub scroll: usize,
+    pub query: String,
+    /// Whether the query is being typed
+    pub searching: bool,
+    /// The line matching the query
+    pub current_match: Option<usize>,
+}
+
+impl LogViewer {
+    pub fn new(scroll: usize) -> Self {
+        Self {
+            scroll,
+            ..Default::default()
+        }
+    }
+
+    pub fn scroll_up(&mut self, lines: usize, log: &LogBuffer) {
+        self.scroll = (self.scroll + lines).min(log.len().saturating_sub(1));
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    /// The index of the bottom line in view
+    fn bottom_line(&self, log: &LogBuffer) -> usize {
+        log.len().saturating_sub(self.scroll + 1)
+    }
+
+    fn show_line(&mut self, index: usize, log: &LogBuffer) {
+        self.scroll = log.len().saturating_sub(index + 1);
+    }
+
+    /// Searches the query backwards from the bottom line in view, including it
+    pub fn search(&mut self, log: &LogBuffer) {
+        if self.query.is_empty() {
+            self.current_match = None;
+            return;
+        }
+        self.current_match = log.rfind(&self.query, self.bottom_line(log) + 1);
+        if let Some(i) = self.current_match {
+            self.show_line(i, log);
+        }
+    }
+
+    pub fn search_older(&mut self, log: &LogBuffer) {
+        let before = self.current_match.unwrap_or(self.bottom_line(log) + 1);
+        if let Some(i) = log.rfind(&self.query, before) {
+            self.current_match = Some(i);
+            self.show_line(i, log);
+        }
+    }
+
+    pub fn search_newer(&mut self, log: &LogBuffer) {
+        let after = self.current_match.unwrap_or(self.bottom_line(log));
+        if let Some(i) = log.find(&self.query, after) {
+            self.current_match = Some(i);
+            self.show_line(i, log);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SchemaPicker {
+    pub schemas: Vec<SchemaInfo>,
+    pub selected: usize,
+}
+
+impl SchemaPicker {
+    /// Starts with `current` selected
+    pub fn new(schemas: Vec<SchemaInfo>, current: Option<&SchemaInfo>) -> Self {
+        let selected = current
+            .and_then(|c| schemas.iter().position(|s| s.id == c.id))
+            .unwrap_or(0);
+        Self { schemas, selected }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.schemas.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn selected(&self) -> Option<&SchemaInfo> {
+        self.schemas.get(self.selected)
+    }
+}
+
+/// A line for typing the argument of a command. The keys don't go through Rime,
+/// so file paths and the like aren't converted by the schema.
+#[derive(Debug, Default)]
+pub struct ArgumentPrompt {
+    /// The name of the command to run with the argument
+    pub command: String,
+    /// Shown before the argument, like `/save`
+    pub label: String,
+    pub input: TextBuffer,
 }
 
-impl TuiApp<CrosstermBackend<Stdout>> {
-    pub fn new() -> io::Result<Self> {
-        let stdout = stdout();
-        let backend = CrosstermBackend::new(stdout);
+impl ArgumentPrompt {
+    /// Starts with `text` typed
+    pub fn new(command: &str, label: String, text: &str) -> Self {
+        let mut input = TextBuffer::default();
+        input.insert_str(text);
+        Self {
+            command: command.into(),
+            label,
+            input,
+        }
+    }
+}
+
+/// Whether [`TuiApp::start`] has put the terminal into raw mode and the alternate screen
+static TERMINAL_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Leaves raw mode and the alternate screen, if [`TuiApp::start`] entered them.
+///
+/// This needs no [`TuiApp`], so it can be called from a panic hook, where the app may be locked.
+pub fn restore_terminal() -> io::Result<()> {
+    if !TERMINAL_TAKEN.swap(false, Ordering::SeqCst) {
+        return Ok(());
+    }
+    disable_raw_mode()?;
+    execute!(
+        open_tty()?,
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableFocusChange,
+        cursor::Show
+    )
+}
+
+/// Opens the controlling terminal for writing.
+///
+/// The TUI is drawn here rather than on stdout, so stdout can be piped or captured.
+pub fn open_tty() -> io::Result<File> {
+    File::options().write(true).open("/dev/tty")
+}
+
+impl TuiApp<CrosstermBackend<BufWriter<File>>> {
+    pub fn new(theme: Theme, layout: LayoutConfig) -> io::Result<Self> {
+        // unbuffered, every escape sequence of a frame would be a write(2) of its own
+        let backend = CrosstermBackend::new(BufWriter::new(open_tty()?));
         let terminal = Terminal::new(backend)?;
+        let ui_data = UiData {
+            candidate_layout: layout.candidate_layout,
+            ..Default::default()
+        };
         Ok(Self {
-            ui_data: Default::default(),
+            ui_data,
             terminal,
+            theme,
+            layout,
+            click_targets: Vec::new(),
         })
     }
 
-    pub fn start(&self) -> io::Result<()> {
+    /// Returns the stream of terminal events. It can be simply dropped if they are
+    /// not needed.
+    pub fn start(&mut self) -> io::Result<Receiver<Event>> {
         enable_raw_mode()?;
-        let mut stdout = stdout();
-        execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+        TERMINAL_TAKEN.store(true, Ordering::SeqCst);
+        execute!(
+            self.terminal.backend_mut(),
+            EnterAlternateScreen,
+            EnableMouseCapture,
+            EnableFocusChange
+        )?;
         // consume terminal key input events
-        // Because we are getting keyboard events from X11 APIs, but not via this,
-        // so if don't do this, when the app is terminated, the screen will leave
+        // Because when we are getting keyboard events from X11 APIs, but not via this,
+        // if don't do this, when the app is terminated, the screen will leave
         // all the text the user just inputted.
         // This method is a bit tricky but just works. And I haven't found a better way (I can't
         // get `event:poll` work).
-        spawn(|| loop {
-            event::read().unwrap();
+        let (sender, receiver) = channel();
+        spawn(move || loop {
+            let event = event::read().unwrap();
+            // the receiver may be dropped; keep consuming anyway
+            let _ = sender.send(event);
         });
-        Ok(())
+        Ok(receiver)
     }
 
     pub fn stop(&mut self) -> io::Result<()> {
-        let terminal = &mut self.terminal;
-        disable_raw_mode()?;
-        execute!(
-            terminal.backend_mut(),
-            LeaveAlternateScreen,
-            DisableMouseCapture
-        )?;
-        terminal.show_cursor()?;
-        Ok(())
+        restore_terminal()
     }
 
     pub fn redraw(&mut self) -> io::Result<()> {
-        self.terminal.draw(|f| Self::ui(&self.ui_data, f))?;
+        self.click_targets.clear();
+        self.terminal.draw(|f| {
+            Self::ui(
+                &self.ui_data,
+                &self.theme,
+                &self.layout,
+                &mut self.click_targets,
+                f,
+            )
+        })?;
         Ok(())
     }
 
-    fn ui<B: Backend>(ui_data: &UiData, f: &mut Frame<B>) {
+    /// Returns what a click or scroll at (`column`, `row`) does, as of the last redraw.
+    /// The most specific target is returned if areas overlap.
+    pub fn click_target(&self, column: u16, row: u16) -> Option<ClickTarget> {
+        self.click_targets
+            .iter()
+            .find(|(r, _)| {
+                (r.x..r.x + r.width).contains(&column) && (r.y..r.y + r.height).contains(&row)
+            })
+            .map(|(_, t)| t.clone())
+    }
+
+    fn ui<B: Backend>(
+        ui_data: &UiData,
+        theme: &Theme,
+        layout: &LayoutConfig,
+        click_targets: &mut Vec<(Rect, ClickTarget)>,
+        f: &mut Frame<B>,
+    ) {
+        let candidates_width = layout.candidates_width.min(100);
+        let log_height = layout.log_height.min(100);
+        let maximized = ui_data.output_maximized;
+        // a maximized Output keeps only the preedit and a candidate bar besides
+        let horizontal = maximized || ui_data.candidate_layout == CandidateLayout::Horizontal;
+        let panes = layout
+            .panes
+            .iter()
+            .copied()
+            .filter(|p| match p {
+                Pane::Log => !ui_data.log_hidden && !maximized,
+                Pane::Status => !maximized,
+                _ => true,
+            })
+            .collect::<Vec<_>>();
+        let rows = layout_rows(&panes, horizontal);
+
+        // the inline candidates take as many lines as they need, up to a limit
+        let inline_candidates = if horizontal {
+            let width = f.size().width.saturating_sub(layout.margin * 2 + 2);
+            inline_candidate_lines(&candidate_labels(ui_data), width)
+        } else {
+            Vec::new()
+        };
+        let bar_height = inline_candidates.len().clamp(1, MAX_INLINE_CANDIDATE_LINES) as u16 + 2;
+
+        let constraints = rows
+            .iter()
+            .map(|r| match r {
+                Row::Pane(Pane::Preedit) => Constraint::Length(3),
+                Row::Pane(Pane::Status) => Constraint::Length(1),
+                Row::Pane(Pane::Candidates) if horizontal => Constraint::Length(bar_height),
+                _ => Constraint::Min(1),
+            })
+            .collect::<Vec<_>>();
         let chunks = Layout::default()
             .direction(Direction::Vertical)
-            .margin(2)
-            .constraints([Constraint::Length(3), Constraint::Min(1)].as_ref())
+            .margin(layout.margin)
+            .constraints(constraints)
             .split(f.size());
 
-        let preedit_chunk = chunks[0];
+        // the vertical candidate list goes beside the first body
+        let mut candidate_column = !horizontal && panes.contains(&Pane::Candidates);
+        for (row, &chunk) in rows.iter().zip(chunks.iter()) {
+            let body = match row {
+                Row::Pane(Pane::Preedit) => {
+                    render_preedit(f, chunk, ui_data, theme);
+                    continue;
+                }
+                Row::Pane(Pane::Status) => {
+                    let status_bar = status_bar(ui_data, chunk, theme, click_targets);
+                    f.render_widget(status_bar, chunk);
+                    continue;
+                }
+                Row::Pane(Pane::Candidates) if horizontal => {
+                    render_inline_candidates(
+                        f,
+                        chunk,
+                        ui_data,
+                        theme,
+                        &inline_candidates,
+                        click_targets,
+                    );
+                    continue;
+                }
+                Row::Pane(Pane::Candidates) => {
+                    render_candidate_list(f, chunk, ui_data, theme, click_targets);
+                    continue;
+                }
+                Row::Pane(_) => continue,
+                Row::Body(body) => body,
+            };
 
-        let input = Paragraph::new(ui_data.preedit.as_ref())
-            .style(Style::default().fg(Color::Yellow))
-            .block(Block::default().borders(Borders::ALL).title("Preedit"));
-        f.render_widget(input, preedit_chunk);
+            let mut body_chunk = chunk;
+            if candidate_column {
+                candidate_column = false;
+                let list_constraint = Constraint::Percentage(candidates_width);
+                let body_constraint = Constraint::Percentage(100 - candidates_width);
+                let (constraints, list_index) = match layout.candidates_side {
+                    Side::Left => ([list_constraint, body_constraint], 0),
+                    Side::Right => ([body_constraint, list_constraint], 1),
+                };
+                let chunks = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints(constraints.as_ref())
+                    .split(chunk);
+                render_candidate_list(f, chunks[list_index], ui_data, theme, click_targets);
+                body_chunk = chunks[1 - list_index];
+            }
 
-        let chunks = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
-            .split(chunks[1]);
+            let constraints = body
+                .iter()
+                .map(|p| match p {
+                    _ if body.len() == 1 => Constraint::Min(1),
+                    Pane::Log => Constraint::Percentage(log_height),
+                    _ => Constraint::Percentage(100 - log_height),
+                })
+                .collect::<Vec<_>>();
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(constraints)
+                .split(body_chunk);
+            for (pane, &chunk) in body.iter().zip(chunks.iter()) {
+                match pane {
+                    Pane::Output => render_output(f, chunk, ui_data, theme),
+                    _ => render_log(f, chunk, ui_data, theme, click_targets),
+                }
+            }
+        }
 
-        let candidates_chunk = chunks[1];
+        if let Some(picker) = &ui_data.schema_picker {
+            let items = picker
+                .schemas
+                .iter()
+                .map(|s| {
+                    let current = ui_data.status.as_ref().map(|x| &x.schema.id) == Some(&s.id);
+                    let mark = if current { "*" } else { " " };
+                    ListItem::new(format!("{} {} ({})", mark, s.name, s.id))
+                })
+                .collect::<Vec<_>>();
+            let list = List::new(items)
+                .block(block(theme, "Schemas [Up/Down, Return, Escape]"))
+                .highlight_style(highlight_style(theme));
+            let mut state = ListState::default();
+            state.select(Some(picker.selected));
+            let area = centered_rect(f.size(), 60, picker.schemas.len() as u16 + 2);
+            f.render_widget(Clear, area);
+            f.render_stateful_widget(list, area, &mut state);
+        }
 
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
-            .split(chunks[0]);
-
-        let message_chunk = chunks[0];
-        let log_chunk = chunks[1];
-
-        let message_width = message_chunk.width - 2 /* border size takes 2 */;
-        let message_height = message_chunk.height - 2;
-        let wrapped_message = wrap_text(&ui_data.output, message_width);
-        let message = Paragraph::new(wrapped_message.1)
-            .block(Block::default().borders(Borders::ALL).title("Output"))
-            .scroll((
-                if wrapped_message.0 > message_height {
-                    wrapped_message.0 - message_height
-                } else {
-                    0
-                },
-                0,
-            ));
-        f.render_widget(message, message_chunk);
+        if let Some(viewer) = &ui_data.log_viewer {
+            click_targets.clear();
+            render_log_viewer(f, viewer, &ui_data.log, theme, click_targets);
+        }
+    }
+}
 
-        let items = ui_data
-            .candidates
-            .iter()
-            .enumerate()
-            .map(|(i, c)| {
-                let label = match &ui_data.select_labels {
-                    None => {
-                        format!("{}.", i + 1)
-                    }
-                    Some(l) => l[i].clone(),
+/// A row of the vertical layout
+enum Row {
+    Pane(Pane),
+    /// Adjacent Output and Log panes, which the vertical candidate list goes beside
+    Body(Vec<Pane>),
+}
+
+/// Groups adjacent Output and Log panes into bodies. The Candidates pane only takes
+/// a row of its own if it's horizontal, or if there's no body to go beside.
+fn layout_rows(panes: &[Pane], horizontal: bool) -> Vec<Row> {
+    let has_body = panes.iter().any(|p| matches!(p, Pane::Output | Pane::Log));
+    let mut rows = Vec::new();
+    for &pane in panes {
+        match (pane, rows.last_mut()) {
+            (Pane::Output | Pane::Log, Some(Row::Body(body))) => body.push(pane),
+            (Pane::Output | Pane::Log, _) => rows.push(Row::Body(vec![pane])),
+            (Pane::Candidates, _) if !horizontal && has_body => {}
+            _ => rows.push(Row::Pane(pane)),
+        }
+    }
+    rows
+}
+
+fn render_preedit<B: Backend>(f: &mut Frame<B>, area: Rect, ui_data: &UiData, theme: &Theme) {
+    if let Some(prompt) = &ui_data.argument_prompt {
+        let title = format!("{} [Return, Escape]", prompt.label);
+        let spans = prompt_spans(&prompt.input, Style::default().fg(theme.preedit));
+        f.render_widget(Paragraph::new(spans).block(block(theme, &title)), area);
+        return;
+    }
+    let mut title = String::from(if ui_data.input_live {
+        "Preedit [live]"
+    } else {
+        "Preedit [paused]"
+    });
+    if let Some(preview) = &ui_data.commit_preview {
+        title.push_str(" | ");
+        title.push_str(preview);
+    }
+    let input = Paragraph::new(preedit_spans(ui_data, theme)).block(block(theme, &title));
+    f.render_widget(input, area);
+}
+
+/// Renders the output, and puts the terminal cursor at its caret
+fn render_output<B: Backend>(f: &mut Frame<B>, area: Rect, ui_data: &UiData, theme: &Theme) {
+    let width = area.width.saturating_sub(2) /* border size takes 2 */;
+    let height = area.height.saturating_sub(2);
+    if width == 0 || height == 0 {
+        f.render_widget(block(theme, "Output"), area);
+        return;
+    }
+    let wrapped_message = wrap_text(ui_data.output.as_str(), ui_data.output.cursor(), width);
+    let (caret_row, caret_column) = wrapped_message.2;
+    // keep the caret visible
+    let scroll = if caret_row >= height {
+        caret_row - height + 1
+    } else {
+        0
+    };
+    let message = Paragraph::new(wrapped_message.1)
+        .block(block(theme, "Output"))
+        .scroll((scroll, 0));
+    f.render_widget(message, area);
+    f.set_cursor(
+        area.x + 1 + caret_column.min(width - 1),
+        area.y + 1 + caret_row - scroll,
+    );
+}
+
+fn render_log<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    ui_data: &UiData,
+    theme: &Theme,
+    click_targets: &mut Vec<(Rect, ClickTarget)>,
+) {
+    let mut title = match ui_data.log_scroll {
+        0 => String::from("Log"),
+        n => format!("Log [{} newer]", n),
+    };
+    let min_severity = ui_data.log.min_severity();
+    if min_severity > Severity::Info {
+        title.push_str(&format!(" [{}+]", min_severity.name()));
+    }
+    render_log_lines(
+        f,
+        area,
+        &ui_data.log,
+        ui_data.log_scroll,
+        None,
+        theme,
+        &title,
+    );
+    click_targets.push((area, ClickTarget::Log));
+}
+
+/// Renders the log lines ending `scroll` lines above the newest, wrapped to fit `area`.
+///
+/// The `highlighted` line, like a search match, is highlighted.
+fn render_log_lines<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    log: &LogBuffer,
+    scroll: usize,
+    highlighted: Option<usize>,
+    theme: &Theme,
+    title: &str,
+) {
+    let width = area.width.saturating_sub(2) as usize;
+    let height = area.height.saturating_sub(2) as usize;
+    let end = log.len().saturating_sub(scroll);
+    // collect from the bottom until the area is filled
+    let mut rows = Vec::new();
+    for i in (0..end).rev() {
+        if rows.len() >= height {
+            break;
+        }
+        let Some(entry) = log.get(i) else {
+            break;
+        };
+        let style = if highlighted == Some(i) {
+            highlight_style(theme)
+        } else {
+            theme.log_style(entry.severity)
+        };
+        for row in wrap_line(&entry.raw, width).into_iter().rev() {
+            rows.push(Spans::from(Span::styled(row, style)));
+        }
+    }
+    rows.reverse();
+    // only the bottom part of a line may fit at the top
+    let skip = rows.len().saturating_sub(height);
+    let log = Paragraph::new(rows.split_off(skip)).block(block(theme, title));
+    f.render_widget(log, area);
+}
+
+/// Wraps `line` into rows of at most `width` columns, by chars
+fn wrap_line(line: &str, width: usize) -> Vec<String> {
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    let mut row_width = 0;
+    for c in line.chars() {
+        let w = c.width().unwrap_or(0);
+        if row_width + w > width && !row.is_empty() {
+            rows.push(std::mem::take(&mut row));
+            row_width = 0;
+        }
+        row.push(c);
+        row_width += w;
+    }
+    rows.push(row);
+    rows
+}
+
+/// Renders the log viewer over the whole screen
+fn render_log_viewer<B: Backend>(
+    f: &mut Frame<B>,
+    viewer: &LogViewer,
+    log: &LogBuffer,
+    theme: &Theme,
+    click_targets: &mut Vec<(Rect, ClickTarget)>,
+) {
+    let area = f.size();
+    let len = log.len();
+    let mut title = format!(
+        "Log {}/{} [Up/Down, PageUp/PageDown, /: search, n/N: older/newer match, Escape]",
+        len.saturating_sub(viewer.scroll),
+        len
+    );
+    if viewer.searching || !viewer.query.is_empty() {
+        title.push_str(&format!(" /{}", viewer.query));
+        if viewer.current_match.is_none() {
+            title.push_str(" (not found)");
+        }
+    }
+    f.render_widget(Clear, area);
+    render_log_lines(
+        f,
+        area,
+        log,
+        viewer.scroll,
+        viewer.current_match,
+        theme,
+        &title,
+    );
+    click_targets.push((area, ClickTarget::Log));
+}
+
+fn block<'a>(theme: &Theme, title: &'a str) -> Block<'a> {
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(theme.border_style())
+        .title(title)
+}
+
+/// Candidates with their labels, like "1. 你好"
+fn candidate_labels(ui_data: &UiData) -> Vec<String> {
+    ui_data
+        .candidates
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let label = match &ui_data.select_labels {
+                None => {
+                    format!("{}.", i + 1)
+                }
+                Some(l) => l[i].clone(),
+            };
+            format!("{} {}{}", label, c.text, c.comment)
+        })
+        .collect()
+}
+
+fn candidates_title(ui_data: &UiData) -> String {
+    if ui_data.candidates.is_empty() || ui_data.page_no == 0 && ui_data.is_last_page {
+        return String::from("Candidates");
+    }
+    let previous = if ui_data.page_no > 0 { "◀" } else { " " };
+    let next = if ui_data.is_last_page { " " } else { "▶" };
+    format!("Candidates {} {} {}", previous, ui_data.page_no + 1, next)
+}
+
+fn highlight_style(theme: &Theme) -> Style {
+    Style::default()
+        .fg(theme.highlight_fg)
+        .bg(theme.highlight_bg)
+}
+
+/// Renders the candidates as a vertical list, one per line
+fn render_candidate_list<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    ui_data: &UiData,
+    theme: &Theme,
+    click_targets: &mut Vec<(Rect, ClickTarget)>,
+) {
+    let items = candidate_labels(ui_data)
+        .into_iter()
+        .zip(&ui_data.candidates)
+        .map(|(label, c)| {
+            let mut item = ListItem::new(label);
+            if c.highlighted {
+                item = item.style(highlight_style(theme));
+            }
+            item
+        })
+        .collect::<Vec<_>>();
+    let title = candidates_title(ui_data);
+    let list = List::new(items).block(block(theme, &title));
+    f.render_stateful_widget(list, area, &mut ListState::default());
+    for i in 0..ui_data
+        .candidates
+        .len()
+        .min(area.height.saturating_sub(2) as usize)
+    {
+        click_targets.push((
+            Rect {
+                x: area.x + 1,
+                y: area.y + 1 + i as u16,
+                width: area.width.saturating_sub(2),
+                height: 1,
+            },
+            ClickTarget::Candidate(i),
+        ));
+    }
+    click_targets.push((area, ClickTarget::Candidates));
+}
+
+/// Lines of inline candidates are truncated beyond this
+const MAX_INLINE_CANDIDATE_LINES: usize = 3;
+
+/// Separates inline candidates
+const INLINE_CANDIDATE_GAP: &str = "  ";
+
+/// Fills lines of `width` columns with as many labelled candidates as fit, and returns
+/// the candidates of each line as (index, column, label).
+/// A candidate wider than a whole line is truncated when rendered.
+fn inline_candidate_lines(labels: &[String], width: u16) -> Vec<Vec<(usize, u16, String)>> {
+    let width = width as usize;
+    let gap = INLINE_CANDIDATE_GAP.width();
+    let mut lines = Vec::new();
+    let mut line: Vec<(usize, u16, String)> = Vec::new();
+    let mut line_width = 0;
+    for (i, label) in labels.iter().enumerate() {
+        let label_width = label.width();
+        if !line.is_empty() && line_width + gap + label_width > width {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0;
+        }
+        if !line.is_empty() {
+            line_width += gap;
+        }
+        line.push((i, line_width as u16, label.clone()));
+        line_width += label_width;
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Renders the candidates inline, as a bar under the preedit
+fn render_inline_candidates<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    ui_data: &UiData,
+    theme: &Theme,
+    lines: &[Vec<(usize, u16, String)>],
+    click_targets: &mut Vec<(Rect, ClickTarget)>,
+) {
+    let inner_width = area.width.saturating_sub(2);
+    let text = lines
+        .iter()
+        .take(MAX_INLINE_CANDIDATE_LINES)
+        .enumerate()
+        .map(|(row, line)| {
+            let mut spans = Vec::new();
+            for (i, column, label) in line {
+                if !spans.is_empty() {
+                    spans.push(Span::raw(INLINE_CANDIDATE_GAP));
+                }
+                let style = if ui_data.candidates[*i].highlighted {
+                    highlight_style(theme)
+                } else {
+                    Style::default()
                 };
+                click_targets.push((
+                    Rect {
+                        x: area.x + 1 + column,
+                        y: area.y + 1 + row as u16,
+                        width: (label.width() as u16).min(inner_width.saturating_sub(*column)),
+                        height: 1,
+                    },
+                    ClickTarget::Candidate(*i),
+                ));
+                spans.push(Span::styled(label.clone(), style));
+            }
+            Spans::from(spans)
+        })
+        .collect::<Vec<_>>();
+    let title = candidates_title(ui_data);
+    f.render_widget(Paragraph::new(text).block(block(theme, &title)), area);
+    click_targets.push((area, ClickTarget::Candidates));
+}
+
+/// Styles the converted text, the selected segment and the unconverted remainder
+/// of the preedit differently, and shows the caret in reverse video.
+fn preedit_spans(ui_data: &UiData, theme: &Theme) -> Spans<'static> {
+    let preedit = ui_data.preedit.as_str();
+    // the indices come from Rime; don't trust them to be char boundaries
+    let clamp = |i: usize| {
+        let mut i = i.min(preedit.len());
+        while !preedit.is_char_boundary(i) {
+            i -= 1;
+        }
+        i
+    };
+    let (sel_start, sel_end) = ui_data.preedit_selection;
+    let sel_end = clamp(sel_end);
+    let sel_start = clamp(sel_start).min(sel_end);
+    let cursor = clamp(ui_data.preedit_cursor);
 
-                let mut item = ListItem::new(format!("{} {}{}", label, c.text, c.comment));
-                if c.highlighted {
-                    item = item.style(Style::default().fg(Color::Black).bg(Color::White));
+    let base = Style::default().fg(theme.preedit);
+    let style_at = |i: usize| {
+        if i < sel_start {
+            base
+        } else if i < sel_end {
+            base.add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+        } else {
+            base.add_modifier(Modifier::DIM)
+        }
+    };
+
+    let mut spans = Vec::new();
+    let mut push = |text: &str, style: Style| {
+        if !text.is_empty() {
+            spans.push(Span::styled(String::from(text), style));
+        }
+    };
+    let boundaries = [0, sel_start, sel_end, preedit.len()];
+    for w in boundaries.windows(2) {
+        let (start, end) = (w[0], w[1]);
+        if (start..end).contains(&cursor) {
+            let caret_end = cursor + preedit[cursor..].chars().next().map_or(0, char::len_utf8);
+            push(&preedit[start..cursor], style_at(start));
+            push(
+                &preedit[cursor..caret_end],
+                style_at(cursor).add_modifier(Modifier::REVERSED),
+            );
+            push(&preedit[caret_end..end], style_at(start));
+        } else {
+            push(&preedit[start..end], style_at(start));
+        }
+    }
+    if cursor == preedit.len() && !preedit.is_empty() {
+        push(" ", base.add_modifier(Modifier::REVERSED));
+    }
+    Spans::from(spans)
+}
+
+/// The text of the argument prompt, with the caret in reverse video
+fn prompt_spans(input: &TextBuffer, style: Style) -> Spans<'static> {
+    let (before, after) = input.as_str().split_at(input.cursor());
+    let caret_len = after.chars().next().map_or(0, char::len_utf8);
+    let (caret, after) = after.split_at(caret_len);
+    let caret = if caret.is_empty() { " " } else { caret };
+    Spans::from(vec![
+        Span::styled(String::from(before), style),
+        Span::styled(String::from(caret), style.add_modifier(Modifier::REVERSED)),
+        Span::styled(String::from(after), style),
+    ])
+}
+
+/// Shows the schema, the common options, the session state, and the number of
+/// warnings and errors logged since the log viewer was last opened.
+///
+/// The options and the badge are clickable; their areas are pushed into `click_targets`.
+fn status_bar(
+    ui_data: &UiData,
+    area: Rect,
+    theme: &Theme,
+    click_targets: &mut Vec<(Rect, ClickTarget)>,
+) -> Paragraph<'static> {
+    let mut spans = Vec::new();
+    let mut push_clickable = |spans: &mut Vec<Span<'static>>, span: Span<'static>, target| {
+        let x = area.x + spans.iter().map(Span::width).sum::<usize>() as u16;
+        click_targets.push((
+            Rect {
+                x,
+                y: area.y,
+                width: span.width() as u16,
+                height: 1,
+            },
+            target,
+        ));
+        spans.push(span);
+    };
+
+    match &ui_data.status {
+        None => spans.push(Span::raw("Schema: -")),
+        Some(status) => {
+            spans.push(Span::raw(format!(
+                "{} ({})",
+                status.schema.name, status.schema.id
+            )));
+            let switch = |on: bool, on_label: &'static str, off_label| {
+                if on {
+                    on_label
+                } else {
+                    off_label
                 }
-                item
-            })
-            .collect::<Vec<_>>();
-        let list =
-            List::new(items).block(Block::default().borders(Borders::ALL).title("Candidates"));
-        f.render_stateful_widget(list, candidates_chunk, &mut ListState::default());
+            };
+            let options = [
+                (
+                    "ascii_mode",
+                    switch(status.is_ascii_mode, "ASCII", "Chinese"),
+                ),
+                ("full_shape", switch(status.is_full_shape, "Full", "Half")),
+                (
+                    "simplification",
+                    switch(status.is_simplified, "Simp", "Trad"),
+                ),
+                (
+                    "ascii_punct",
+                    switch(status.is_ascii_punct, "ASCII punct", "CJK punct"),
+                ),
+            ];
+            for (option, label) in options {
+                spans.push(Span::raw(" | "));
+                let label = Span::styled(
+                    format!("[{}]", label),
+                    Style::default().add_modifier(Modifier::BOLD),
+                );
+                push_clickable(&mut spans, label, ClickTarget::ToggleOption(option));
+            }
+            if status.is_composing {
+                spans.push(Span::raw(" | Composing"));
+            }
+            if status.is_disabled {
+                spans.push(Span::raw(" | Disabled"));
+            }
+        }
+    }
+
+    if ui_data.unseen_warnings > 0 || ui_data.unseen_errors > 0 {
+        spans.push(Span::raw(" | "));
+        let mut badge = Vec::new();
+        if ui_data.unseen_warnings > 0 {
+            badge.push(Span::styled(
+                format!("W:{}", ui_data.unseen_warnings),
+                Style::default().fg(theme.log_warning),
+            ));
+        }
+        if ui_data.unseen_errors > 0 {
+            if !badge.is_empty() {
+                badge.push(Span::raw(" "));
+            }
+            badge.push(Span::styled(
+                format!("E:{}", ui_data.unseen_errors),
+                Style::default().fg(theme.log_error),
+            ));
+        }
+        for span in badge {
+            push_clickable(&mut spans, span, ClickTarget::LogBadge);
+        }
+    }
+    Paragraph::new(Spans::from(spans))
+}
 
-        let last_line = ui_data.log.last();
-        let last_line = last_line.map(|x| x.as_str()).unwrap_or("");
-        let log = Paragraph::new(last_line)
-            .block(Block::default().borders(Borders::ALL).title("Log"))
-            .wrap(Wrap { trim: false });
-        f.render_widget(log, log_chunk);
+/// A `width_percent` wide area in the middle of `area`, at most `height` high
+fn centered_rect(area: Rect, width_percent: u16, height: u16) -> Rect {
+    // in u32, as a wide terminal times the percentage overflows u16
+    let width = (u32::from(area.width) * u32::from(width_percent.min(100)) / 100) as u16;
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
     }
 }
 
-fn wrap_text(text: &str, width: u16) -> (u16, String) {
+/// Returns the wrapped line count, the wrapped text, and the (row, column)
+/// position of byte index `cursor` in the wrapped text.
+fn wrap_text(text: &str, cursor: usize, width: u16) -> (u16, String, (u16, u16)) {
     let mut wrapped_lines = String::new();
     let mut line_count = 0_u16;
+    let mut caret = None;
 
     let mut line = String::new();
-    for c in text.chars() {
+    for (i, c) in text.char_indices() {
+        if i == cursor {
+            caret = Some((line_count, line.width() as u16));
+        }
         if c == '\n' {
             wrapped_lines.push_str(&line);
             wrapped_lines.push('\n');
@@ -172,16 +1134,17 @@ fn wrap_text(text: &str, width: u16) -> (u16, String) {
             continue;
         }
         line.push(c);
-        if line.width() > width as usize - c.width().unwrap_or(1) {
+        if line.width() > (width as usize).saturating_sub(c.width().unwrap_or(1)) {
             wrapped_lines.push_str(&line);
             wrapped_lines.push('\n');
             line.clear();
             line_count += 1;
         }
     }
+    let caret = caret.unwrap_or((line_count, line.width() as u16));
     if !line.is_empty() {
         wrapped_lines.push_str(&line);
         line_count += 1;
     }
-    (line_count, wrapped_lines)
+    (line_count, wrapped_lines, caret)
 }
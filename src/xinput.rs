@@ -1,27 +1,44 @@
+use std::cell::Cell;
 use std::ffi::CString;
+use std::io;
 use std::mem::MaybeUninit;
-use std::os::raw::{c_int, c_uchar};
-use std::ptr::null;
+use std::os::raw::{c_int, c_uchar, c_ulong};
+use std::ptr::{null, null_mut};
 
 use x11::xinput2::{
     XIAllDevices, XIDeviceEvent, XIEventMask, XISelectEvents, XISetMask, XI_KeyPress,
     XI_KeyRelease, XI_LASTEVENT,
 };
 use x11::xlib::{
-    Display, False, GenericEvent, XDefaultRootWindow, XEvent, XFreeEventData, XGenericEventCookie,
-    XGetEventData, XNextEvent, XOpenDisplay, XQueryExtension, XSync,
+    Atom, Display, False, GenericEvent, PropertyChangeMask, PropertyNotify, Success, Window,
+    XCloseDisplay, XDefaultRootWindow, XEvent, XFree, XFreeEventData, XGenericEventCookie,
+    XGetEventData, XGetWindowProperty, XInternAtom, XNextEvent, XOpenDisplay, XQueryExtension,
+    XQueryTree, XSelectInput, XSync, XA_WINDOW,
 };
 
 use cstr::cstr;
 use libc::{calloc, size_t};
+use rime_api::KeyEvent;
+
+use crate::input::InputSource;
+use crate::key_event::KeyEventResolver;
 
 pub struct XInput {
     xi_opcode: c_int,
     display: *mut Display,
+    root: Window,
+    net_active_window: Atom,
+    /// The terminal window rime-tui runs in
+    own_window: Option<Window>,
+    focused: Cell<bool>,
+    resolver: KeyEventResolver,
 }
 
 impl XInput {
-    pub fn new(display: Option<&str>) -> XInput {
+    /// Key events are only delivered while `own_window` (or the top-level window containing it)
+    /// is the active window. If it's `None`, `$WINDOWID` is used, or else the window that
+    /// is active at startup is assumed to be the terminal running rime-tui.
+    pub fn new(display: Option<&str>, own_window: Option<Window>) -> io::Result<XInput> {
         let mut xi_opcode = 0 as c_int;
         unsafe {
             let mut event = 0 as c_int;
@@ -29,12 +46,16 @@ impl XInput {
             let display = match display {
                 None => XOpenDisplay(null()),
                 Some(d) => {
-                    let d = CString::new(d).unwrap();
+                    let d = CString::new(d)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
                     XOpenDisplay(d.as_ptr())
                 }
             };
             if display.is_null() {
-                panic!("Unable to connect to X server");
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Unable to connect to X server",
+                ));
             }
 
             if XQueryExtension(
@@ -45,7 +66,11 @@ impl XInput {
                 &mut error as *mut c_int,
             ) == 0
             {
-                panic!("X Input extension not available");
+                XCloseDisplay(display);
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "X Input extension not available",
+                ));
             }
 
             let window = XDefaultRootWindow(display);
@@ -60,13 +85,118 @@ impl XInput {
             XISetMask(m, XI_KeyRelease);
 
             XISelectEvents(display, window, &mut mask as *mut XIEventMask, 1);
+            // track `_NET_ACTIVE_WINDOW` changes
+            XSelectInput(display, window, PropertyChangeMask);
             XSync(display, False as c_int);
 
-            Self { xi_opcode, display }
+            let net_active_window = XInternAtom(
+                display,
+                cstr!("_NET_ACTIVE_WINDOW").as_ptr(),
+                False as c_int,
+            );
+
+            let mut input = Self {
+                xi_opcode,
+                display,
+                root: window,
+                net_active_window,
+                own_window: own_window.or_else(window_id_from_env),
+                focused: Cell::new(true),
+                resolver: KeyEventResolver::new(),
+            };
+            if input.own_window.is_none() {
+                input.own_window = input.active_window();
+            }
+            input.update_focus();
+            Ok(input)
+        }
+    }
+
+    /// Whether the terminal window of rime-tui is focused, i.e. key events are delivered
+    pub fn is_focused(&self) -> bool {
+        self.focused.get()
+    }
+
+    fn update_focus(&mut self) {
+        let focused = match (self.own_window, self.active_window()) {
+            (Some(own), Some(active)) => self.is_window_within(own, active),
+            // EWMH is not supported by the window manager, or the own window is unknown
+            _ => true,
+        };
+        if self.focused.replace(focused) != focused {
+            // modifier releases typed into other windows were dropped, so don't keep them held
+            self.resolver.reset();
+        }
+    }
+
+    fn active_window(&self) -> Option<Window> {
+        unsafe {
+            let mut actual_type: Atom = 0;
+            let mut actual_format = 0 as c_int;
+            let mut item_count: c_ulong = 0;
+            let mut bytes_after: c_ulong = 0;
+            let mut prop: *mut c_uchar = null_mut();
+            let status = XGetWindowProperty(
+                self.display,
+                self.root,
+                self.net_active_window,
+                0,
+                1,
+                False as c_int,
+                XA_WINDOW,
+                &mut actual_type,
+                &mut actual_format,
+                &mut item_count,
+                &mut bytes_after,
+                &mut prop,
+            );
+            if status != Success as c_int || prop.is_null() {
+                return None;
+            }
+            let window = if actual_type == XA_WINDOW && item_count == 1 {
+                Some(*(prop as *const Window))
+            } else {
+                None
+            };
+            XFree(prop as *mut _);
+            window.filter(|&w| w != 0)
+        }
+    }
+
+    /// Checks whether `window` is `ancestor` itself or one of its descendants.
+    fn is_window_within(&self, mut window: Window, ancestor: Window) -> bool {
+        unsafe {
+            loop {
+                if window == ancestor {
+                    return true;
+                }
+                if window == self.root || window == 0 {
+                    return false;
+                }
+                let mut root: Window = 0;
+                let mut parent: Window = 0;
+                let mut children: *mut Window = null_mut();
+                let mut children_count = 0_u32;
+                if XQueryTree(
+                    self.display,
+                    window,
+                    &mut root,
+                    &mut parent,
+                    &mut children,
+                    &mut children_count,
+                ) == 0
+                {
+                    return false;
+                }
+                if !children.is_null() {
+                    XFree(children as *mut _);
+                }
+                window = parent;
+            }
         }
     }
 
-    pub fn next_event(&self) -> Option<(XGenericEventCookie, XIDeviceEvent)> {
+    pub fn next_device_event(&mut self) -> Option<(XGenericEventCookie, XIDeviceEvent)> {
         let display = self.display;
         unsafe {
             let ev = MaybeUninit::<XEvent>::uninit();
@@ -76,9 +206,16 @@ impl XInput {
 
             let mut result = None;
 
+            if ev.get_type() == PropertyNotify && ev.property.atom == self.net_active_window {
+                self.update_focus();
+                return None;
+            }
+
             if XGetEventData(display, cookie) != 0
                 && (*cookie).type_ == GenericEvent
                 && (*cookie).extension == self.xi_opcode
+                // drop keys typed into other windows
+                && self.focused.get()
             {
                 let cookie = &*cookie;
                 let event = &*(cookie.data as *const XIDeviceEvent);
@@ -92,3 +229,29 @@ impl XInput {
         }
     }
 }
+
+impl InputSource for XInput {
+    fn next_event(&mut self) -> io::Result<Option<KeyEvent>> {
+        Ok(self
+            .next_device_event()
+            .map(|(_, e)| self.resolver.resolve(&e)))
+    }
+
+    fn is_live(&self) -> bool {
+        self.is_focused()
+    }
+}
+
+fn window_id_from_env() -> Option<Window> {
+    let id = std::env::var("WINDOWID").ok()?;
+    parse_window_id(&id)
+}
+
+/// Parses a window ID in decimal or `0x`-prefixed hexadecimal form
+pub fn parse_window_id(id: &str) -> Option<Window> {
+    let id = id.trim();
+    match id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")) {
+        Some(hex) => Window::from_str_radix(hex, 16).ok(),
+        None => id.parse().ok(),
+    }
+}